@@ -3,6 +3,7 @@ use std::sync::{Arc, RwLock};
 use std::fmt;
 use std::slice;
 use std::ops::Deref;
+use std::path::PathBuf;
 
 use typemap::TypeMap;
 
@@ -24,10 +25,12 @@ pub struct Data {
 
     // TODO: not a fan of exposing the Arc
     /// Arbitrary, bind-level data
+    #[allow(bare_trait_objects)]
     pub extensions: Arc<RwLock<TypeMap<::typemap::CloneAny + Sync + Send>>>,
 }
 
 impl Data {
+    #[allow(clippy::redundant_field_names)]
     pub fn new(name: String, configuration: Arc<Configuration>) -> Data {
         Data {
             name: name,
@@ -46,6 +49,9 @@ impl Data {
 pub struct Bind {
     items: Vec<Item>,
     data: Arc<Data>,
+
+    /// Every file read by an item that was ever attached.
+    sources: Vec<PathBuf>,
 }
 
 impl Bind {
@@ -53,14 +59,27 @@ impl Bind {
         Bind {
             items: Vec::new(),
             data: Arc::new(data),
+            sources: Vec::new(),
         }
     }
 
     pub fn attach(&mut self, mut item: Item) {
+        if let Some(source) = item.route().reading() {
+            self.sources.push(source.to_path_buf());
+        }
+
         item.attach_to(self.data.clone());
         self.items.push(item);
     }
 
+    /// The files read by every item attached to the bind.
+    ///
+    /// This includes the items that were later removed from the bind,
+    /// e.g. by `bind::retain`, since they still influenced the result.
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
     /// Access the bind data as an `Arc`
     pub fn data(&self) -> &Data {
         &self.data
@@ -99,6 +118,7 @@ impl Bind {
 impl Deref for Bind {
     type Target = Data;
 
+    #[allow(clippy::needless_lifetimes)]
     fn deref<'a>(&'a self) -> &'a Data {
         &self.data
     }
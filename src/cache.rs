@@ -0,0 +1,390 @@
+//! Persistent build cache.
+//!
+//! The cache records a fingerprint of every file in the input directory,
+//! along with the files each rule read and wrote and the rules it
+//! depended on during the last build. An incremental build consults it
+//! to determine which rules actually need to run again.
+//!
+//! Statistics about each rule's last build are recorded alongside
+//! the cache after every incremental build, and after any other build
+//! that asks for them.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use toml;
+
+use bind::Bind;
+use dependency::Graph;
+use pattern;
+use rule::Rule;
+use support;
+use util::handle::bind::Selections;
+
+/// The name of the file within the cache directory.
+static FILE_NAME: &str = "build.toml";
+
+/// The name of the statistics file within the cache directory.
+static STATISTICS_FILE_NAME: &str = "statistics.toml";
+
+/// The state of an input file at the time it was enumerated.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Fingerprint {
+    size: u64,
+    seconds: u64,
+    nanoseconds: u32,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> io::Result<Fingerprint> {
+        let metadata = fs::metadata(path)?;
+
+        let modified =
+            metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(Fingerprint {
+            size: metadata.len(),
+            seconds: modified.as_secs(),
+            nanoseconds: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// The fingerprints of the input files, keyed by their path
+/// relative to the input directory.
+pub type Fingerprints = BTreeMap<String, Fingerprint>;
+
+/// Fingerprint each of the given paths.
+///
+/// The paths are expected to be rooted at `input`, as they are
+/// when enumerated by the scheduler.
+pub fn fingerprint(input: &Path, paths: &[PathBuf]) -> Fingerprints {
+    paths.iter()
+        .filter_map(|path| {
+            let relative = path.strip_prefix(input).unwrap_or(path);
+
+            Fingerprint::of(path).ok()
+                .map(|print| (relative.to_string_lossy().into_owned(), print))
+        })
+        .collect()
+}
+
+/// What a rule did the last time it ran.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Entry {
+    /// The rules this rule depended on.
+    dependencies: BTreeSet<String>,
+
+    /// The input files read by the rule's items, relative to
+    /// the input directory.
+    sources: BTreeSet<String>,
+
+    /// The files written by the rule's items, relative to
+    /// the output directory.
+    outputs: BTreeSet<String>,
+
+    /// The textual form of the patterns the rule selected its items
+    /// with, or `None` if any of them doesn't have one.
+    #[serde(default)]
+    selections: Option<Vec<String>>,
+}
+
+impl Entry {
+    fn from_bind(rule: &Rule, bind: &Bind) -> Entry {
+        let sources =
+            bind.sources().iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+
+        let outputs =
+            bind.items().iter()
+            .filter_map(|item| item.route().writing())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+
+        let selections =
+            bind.extensions.read().unwrap()
+            .get::<Selections>()
+            .map_or(Some(vec![]), |patterns| {
+                patterns.iter().map(|pattern| pattern.describe()).collect()
+            });
+
+        Entry {
+            dependencies: rule.dependencies().iter().cloned().collect(),
+            sources,
+            outputs,
+            selections,
+        }
+    }
+
+    /// Whether the rule would select any of the given paths,
+    /// which are relative to the input directory.
+    ///
+    /// Without the rule's patterns there's no telling, so
+    /// it's assumed to select any path.
+    fn selects_any(&self, paths: &[&str]) -> bool {
+        if paths.is_empty() {
+            return false;
+        }
+
+        let selections = match self.selections {
+            Some(ref selections) => selections,
+            None => return true,
+        };
+
+        selections.iter().any(|source| {
+            match pattern::parse(source) {
+                Ok(pattern) => paths.iter().any(|path| pattern.matches(Path::new(path))),
+                Err(_) => true,
+            }
+        })
+    }
+}
+
+/// How a rule fared the last time it was built.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Statistics {
+    /// The number of items in the rule's bind.
+    pub items: usize,
+
+    /// How long the rule's handler took.
+    pub duration: Duration,
+}
+
+/// The statistics of every rule that finished, keyed by rule name.
+pub type RuleStatistics = BTreeMap<String, Statistics>;
+
+/// Load the statistics recorded in the given cache directory.
+pub fn load_statistics(directory: &Path) -> ::Result<RuleStatistics> {
+    read(&directory.join(STATISTICS_FILE_NAME)).map(Option::unwrap_or_default)
+}
+
+/// Record the statistics into the given cache directory.
+pub fn save_statistics(directory: &Path, statistics: &RuleStatistics) -> ::Result<()> {
+    write(directory, &directory.join(STATISTICS_FILE_NAME), statistics)
+}
+
+/// Read a TOML file from the cache directory.
+///
+/// Returns `None` if it doesn't exist.
+fn read<T>(path: &Path) -> ::Result<Option<T>>
+where T: DeserializeOwned {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(::Error::Io(e)),
+    };
+
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    toml::from_str(&contents)
+        .map(Some)
+        .map_err(|e| {
+            let message = format!("could not parse the build cache {:?}: {}", path, e);
+            ::Error::Io(io::Error::new(io::ErrorKind::InvalidData, message))
+        })
+}
+
+/// Write a TOML file into the cache directory.
+fn write<T>(directory: &Path, path: &Path, value: &T) -> ::Result<()>
+where T: ?Sized + Serialize {
+    let contents = toml::to_string(value)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+    support::mkdir_p(directory)?;
+
+    File::create(path)?
+        .write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+/// The persistent build cache.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Cache {
+    /// Fingerprints of every input file seen by the last build.
+    inputs: Fingerprints,
+
+    /// The entry of every rule that finished successfully.
+    rules: BTreeMap<String, Entry>,
+}
+
+impl Cache {
+    /// The path of the cache file within the cache directory.
+    pub fn path(directory: &Path) -> PathBuf {
+        directory.join(FILE_NAME)
+    }
+
+    /// Load the cache from the given cache directory.
+    ///
+    /// Returns `None` if there is no cache yet.
+    pub fn load(directory: &Path) -> ::Result<Option<Cache>> {
+        read(&Cache::path(directory))
+    }
+
+    /// Persist the cache into the given cache directory.
+    pub fn save(&self, directory: &Path) -> ::Result<()> {
+        write(directory, &Cache::path(directory), self)
+    }
+
+    /// Remove the cache file from the given cache directory, if any.
+    pub fn invalidate(directory: &Path) -> ::Result<()> {
+        match fs::remove_file(Cache::path(directory)) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(::Error::Io(e)),
+        }
+    }
+
+    /// Determine the rules that need to run.
+    ///
+    /// A rule is stale if it never finished, if its dependencies changed,
+    /// if any file it read was modified or removed, if any file it
+    /// wrote is missing, or if any file added to the input directory
+    /// matches the patterns it selected its items with.
+    ///
+    /// Every rule that transitively depends on a stale rule is stale as
+    /// well. Stale rules need the binds of their dependencies, which
+    /// aren't cached, so those are run again in full too, even if
+    /// they are fresh.
+    pub fn outdated(&self,
+                    rules: &[Arc<Rule>],
+                    graph: &Graph<String>,
+                    inputs: &Fingerprints,
+                    output: &Path)
+    -> ::Result<BTreeSet<String>> {
+        let added =
+            inputs.keys()
+            .filter(|path| !self.inputs.contains_key(*path))
+            .map(|path| &path[..])
+            .collect::<Vec<&str>>();
+
+        let stale =
+            rules.iter()
+            .filter(|rule| {
+                let entry = match self.rules.get(rule.name()) {
+                    Some(entry) => entry,
+                    None => return true,
+                };
+
+                let dependencies =
+                    rule.dependencies().iter().cloned().collect::<BTreeSet<_>>();
+
+                entry.dependencies != dependencies
+                || entry.sources.iter().any(|source| {
+                       inputs.get(source) != self.inputs.get(source)
+                       || !inputs.contains_key(source)
+                   })
+                || entry.outputs.iter().any(|out| !output.join(out).exists())
+                || entry.selects_any(&added)
+            })
+            .map(|rule| String::from(rule.name()))
+            .collect::<Vec<String>>();
+
+        let affected = graph.resolve(stale)?.into_iter().collect::<Vec<String>>();
+
+        Ok(graph.dependencies_closure(affected))
+    }
+
+    /// Record the outcome of a build.
+    ///
+    /// Rules that finished replace their previous entries. Rules that were
+    /// scheduled but didn't finish are forgotten so that the next build
+    /// runs them again. Files that were written by a rule that has since
+    /// been removed, or that a rule no longer produces, are removed from
+    /// the output directory.
+    ///
+    /// Rules that weren't scheduled, e.g. when building a subset of the
+    /// rules, are forgotten as well if the inputs changed in a way that
+    /// would make them stale, since the new fingerprints would otherwise
+    /// hide those changes from the next build.
+    ///
+    /// Returns the outputs that were removed.
+    pub fn update(&mut self,
+                  rules: &[Arc<Rule>],
+                  scheduled: &BTreeSet<String>,
+                  finished: &BTreeMap<String, Arc<Bind>>,
+                  inputs: Fingerprints,
+                  output: &Path)
+    -> ::Result<Vec<PathBuf>> {
+        let registered =
+            rules.iter()
+            .map(|rule| (String::from(rule.name()), rule))
+            .collect::<HashMap<_, _>>();
+
+        let mut previous = BTreeSet::new();
+
+        let removed =
+            self.rules.keys()
+            .filter(|name| !registered.contains_key(*name))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        for name in removed {
+            let entry = self.rules.remove(&name).unwrap();
+            previous.extend(entry.outputs);
+        }
+
+        for name in scheduled {
+            if let Some(entry) = self.rules.remove(name) {
+                previous.extend(entry.outputs);
+            }
+
+            if let (Some(bind), Some(rule)) = (finished.get(name), registered.get(name)) {
+                self.rules.insert(name.clone(), Entry::from_bind(rule, bind));
+            }
+        }
+
+        // an output may have moved from one rule to another
+        for entry in self.rules.values() {
+            for out in &entry.outputs {
+                previous.remove(out);
+            }
+        }
+
+        let mut removed = Vec::new();
+
+        for out in previous {
+            let path = output.join(out);
+
+            if path.is_file() {
+                fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+
+        let added =
+            inputs.keys()
+            .filter(|path| !self.inputs.contains_key(*path))
+            .map(|path| &path[..])
+            .collect::<Vec<&str>>();
+
+        let stale =
+            self.rules.iter()
+            .filter(|&(name, entry)| {
+                !scheduled.contains(name)
+                && (entry.selects_any(&added) || entry.sources.iter().any(|source| {
+                    inputs.get(source) != self.inputs.get(source)
+                }))
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>();
+
+        for name in stale {
+            self.rules.remove(&name);
+        }
+
+        self.inputs = inputs;
+
+        Ok(removed)
+    }
+}
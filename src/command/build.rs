@@ -1,42 +1,101 @@
-use docopt::Docopt;
+use std::path::Path;
 
 use site::Site;
-use command::Command;
-use configuration::Configuration;
+use command::{Arguments, Command};
+use event::Json;
 
 #[derive(Deserialize, Debug)]
 struct Options {
     flag_jobs: Option<usize>,
     flag_verbose: bool,
+    flag_incremental: bool,
+    flag_statistics: bool,
+    flag_keep_going: bool,
+    flag_prune: bool,
+    flag_atomic: bool,
+    flag_json: bool,
+    flag_rule: Vec<String>,
+    flag_config: Option<String>,
 }
 
+#[allow(clippy::redundant_static_lifetimes)]
 static USAGE: &'static str = "
 Usage:
-    diecast build [options]
+    diecast {command} [options] [--rule NAME]...
 
 Options:
     -h, --help          Print this message
     -j N, --jobs N      Number of jobs to run in parallel
     -v, --verbose       Use verbose output
+    -c FILE, --config FILE
+                        Use this configuration file
+    -k, --keep-going    Build as much as possible and report every failure
+    --incremental       Only rebuild what changed since the last build
+                        and the rules that those depend on
+    --statistics        Record how each rule fared, for the graph command
+    --prune             Build in place and then remove stale output
+    --atomic            Only replace the output if the build succeeds
+    --json              Report build events as JSON lines
+    -r NAME, --rule NAME
+                        Only build this rule and the rules it depends on
 ";
 
-pub struct Build;
+pub struct Build {
+    /// The rules to build, or every rule if empty
+    rules: Vec<String>,
+}
 
 impl Build {
-    pub fn configure(&mut self, configuration: &mut Configuration) {
-        // 1. merge options into configuration; options overrides config
-        // 2. construct site from configuration
-        // 3. build site
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Build {
+        Build {
+            rules: vec![],
+        }
+    }
+
+    pub fn configure(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
+
+        if options.flag_json {
+            site.events_mut().clear();
+            site.observe(Json::stdout());
+        }
 
-        let options: Options = Docopt::new(USAGE)
-            .and_then(|d| d.help(true).deserialize())
-            .unwrap_or_else(|e| e.exit());
+        let configuration = site.configuration_mut();
+
+        configuration.load(options.flag_config.as_ref().map(Path::new))?;
 
         if let Some(jobs) = options.flag_jobs {
             configuration.threads = jobs;
         }
 
-        configuration.is_verbose = options.flag_verbose;
+        if options.flag_verbose {
+            configuration.is_verbose = true;
+        }
+
+        if options.flag_incremental {
+            configuration.is_incremental = true;
+        }
+
+        if options.flag_statistics {
+            configuration.is_recording_statistics = true;
+        }
+
+        if options.flag_keep_going {
+            configuration.is_keep_going = true;
+        }
+
+        if options.flag_prune {
+            configuration.is_pruning = true;
+        }
+
+        if options.flag_atomic {
+            configuration.is_atomic = true;
+        }
+
+        self.rules = options.flag_rule;
+
+        Ok(())
     }
 }
 
@@ -45,8 +104,17 @@ impl Command for Build {
         "Build the site"
     }
 
-    fn run(&mut self, site: &mut Site) -> ::Result<()> {
-        self.configure(site.configuration_mut());
-        site.build()
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site, arguments)?;
+
+        if self.rules.is_empty() {
+            site.build()
+        } else {
+            site.build_rules(&self.rules)
+        }
     }
 }
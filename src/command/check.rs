@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use command::{Arguments, Command};
+use configuration::Configuration;
+use site::Site;
+
+#[derive(Deserialize, Debug)]
+struct Options {
+    flag_jobs: Option<usize>,
+    flag_verbose: bool,
+    flag_config: Option<String>,
+}
+
+static USAGE: &str = "
+Usage:
+    diecast {command} [options]
+
+Options:
+    -h, --help          Print this message
+    -j N, --jobs N      Number of jobs to run in parallel
+    -v, --verbose       Use verbose output
+    -c FILE, --config FILE
+                        Use this configuration file
+
+This validates the rules' dependencies and runs every rule
+without writing to the output directory, then reports every
+error that occurred.
+";
+
+pub struct Check;
+
+impl Check {
+    pub fn configure(&mut self, configuration: &mut Configuration, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
+
+        configuration.load(options.flag_config.as_ref().map(Path::new))?;
+
+        if let Some(jobs) = options.flag_jobs {
+            configuration.threads = jobs;
+        }
+
+        if options.flag_verbose {
+            configuration.is_verbose = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl Command for Check {
+    fn description(&self) -> &'static str {
+        "Check the site for errors without writing output"
+    }
+
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site.configuration_mut(), arguments)?;
+        site.check()?;
+
+        println!("no problems found");
+
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
-use docopt::Docopt;
+use std::path::Path;
 
-use command::Command;
+use command::{Arguments, Command};
 use configuration::Configuration;
 use site::Site;
 
@@ -8,36 +8,43 @@ use site::Site;
 struct Options {
     flag_verbose: bool,
     flag_ignore_hidden: bool,
+    flag_config: Option<String>,
 }
 
-// TODO
-// the help message includes the wrong command
-// e.g. if someone did:
-//
-//     .command("mess", clean)
-//
-// the `diecast help mess` will still show the `clean` command
+#[allow(clippy::redundant_static_lifetimes)]
 static USAGE: &'static str = "
 Usage:
-    diecast clean [options]
+    diecast {command} [options]
 
 Options:
     -h, --help            Print this message
     -v, --verbose         Use verbose output
+    -c FILE, --config FILE
+                          Use this configuration file
     -i, --ignore-hidden   Don't clean out hidden files and directories
 
-This removes the output directory.
+This removes the contents of the output directory, except for the
+paths matched by the configured keep pattern, and, with
+--ignore-hidden, the hidden files and directories at its top level.
 ";
 
 pub struct Clean;
 
 impl Clean {
-    pub fn configure(&mut self, configuration: &mut Configuration) {
-        let options: Options = Docopt::new(USAGE)
-            .and_then(|d| d.help(true).deserialize())
-            .unwrap_or_else(|e| e.exit());
+    pub fn configure(&mut self, configuration: &mut Configuration, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
 
-        configuration.ignore_hidden = options.flag_ignore_hidden;
+        configuration.load(options.flag_config.as_ref().map(Path::new))?;
+
+        if options.flag_verbose {
+            configuration.is_verbose = true;
+        }
+
+        if options.flag_ignore_hidden {
+            configuration.ignore_hidden = true;
+        }
+
+        Ok(())
     }
 }
 
@@ -46,18 +53,21 @@ impl Command for Clean {
         "Remove output directory"
     }
 
-    fn run(&mut self, site: &mut Site) -> ::Result<()> {
-        self.configure(site.configuration_mut());
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site.configuration_mut(), arguments)?;
 
         let target = &site.configuration().output;
 
         if target.exists() {
-            println!("removing {:?}", target);
+            println!("cleaning {:?}", target);
         } else {
             println!("nothing to remove");
         }
 
-        // TODO: clean return Result?
         site.clean()
     }
 }
@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use command::{Arguments, Command};
+use configuration::Configuration;
+use site::Site;
+
+#[derive(Deserialize, Debug)]
+struct Options {
+    flag_config: Option<String>,
+}
+
+static USAGE: &str = "
+Usage:
+    diecast {command} [options]
+
+Options:
+    -h, --help            Print this message
+    -c FILE, --config FILE
+                          Use this configuration file
+
+This prints the effective configuration, after the configuration
+file and the DIECAST_* environment variables have been applied.
+";
+
+pub struct Config;
+
+impl Config {
+    pub fn configure(&mut self, configuration: &mut Configuration, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
+
+        configuration.load(options.flag_config.as_ref().map(Path::new))
+    }
+}
+
+impl Command for Config {
+    fn description(&self) -> &'static str {
+        "Print the effective configuration"
+    }
+
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site.configuration_mut(), arguments)?;
+        print!("{}", site.configuration().to_toml());
+        Ok(())
+    }
+}
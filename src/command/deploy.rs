@@ -1,23 +1,27 @@
-use docopt::Docopt;
+use std::path::Path;
 
 use site::Site;
-use command::Command;
+use command::{Arguments, Command};
 use configuration::Configuration;
 
 #[derive(Deserialize, Debug)]
 struct Options {
     flag_jobs: Option<usize>,
     flag_verbose: bool,
+    flag_config: Option<String>,
 }
 
+#[allow(clippy::redundant_static_lifetimes)]
 static USAGE: &'static str = "
 Usage:
-    diecast deploy [options]
+    diecast {command} [options]
 
 Options:
     -h, --help          Print this message
     -j N, --jobs N      Number of jobs to run in parallel
     -v, --verbose       Use verbose output
+    -c FILE, --config FILE
+                        Use this configuration file
 ";
 
 pub struct Deploy<P>
@@ -27,26 +31,27 @@ where P: Fn(&Site) -> ::Result<()> {
 
 impl<P> Deploy<P>
 where P: Fn(&Site) -> ::Result<()> {
+    #[allow(clippy::redundant_field_names)]
     pub fn new(procedure: P) -> Deploy<P> {
         Deploy {
             procedure: procedure,
         }
     }
 
-    pub fn configure(&mut self, configuration: &mut Configuration) {
-        // 1. merge options into configuration; options overrides config
-        // 2. construct site from configuration
-        // 3. build site
+    pub fn configure(&mut self, configuration: &mut Configuration, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
 
-        let options: Options = Docopt::new(USAGE)
-            .and_then(|d| d.help(true).deserialize())
-            .unwrap_or_else(|e| e.exit());
+        configuration.load(options.flag_config.as_ref().map(Path::new))?;
 
         if let Some(jobs) = options.flag_jobs {
             configuration.threads = jobs;
         }
 
-        configuration.is_verbose = options.flag_verbose;
+        if options.flag_verbose {
+            configuration.is_verbose = true;
+        }
+
+        Ok(())
     }
 }
 
@@ -56,8 +61,12 @@ where P: Fn(&Site) -> ::Result<()> {
         "Deploy the site"
     }
 
-    fn run(&mut self, site: &mut Site) -> ::Result<()> {
-        self.configure(site.configuration_mut());
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site.configuration_mut(), arguments)?;
         site.build()?;
         (self.procedure)(site)
     }
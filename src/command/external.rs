@@ -0,0 +1,117 @@
+//! External commands.
+//!
+//! A command that isn't registered is looked up on `PATH` as a program
+//! named `diecast-<command>`, as cargo does, so that site-specific
+//! tooling can be added without rebuilding the generator.
+//!
+//! The program receives the arguments that follow the command name.
+//! The effective configuration is exported through the same environment
+//! variables that configure the build:
+//!
+//! * `DIECAST_INPUT`: the input directory
+//! * `DIECAST_OUTPUT`: the output directory
+//! * `DIECAST_CACHE`: the cache directory
+//! * `DIECAST_CONFIG`: the configuration file, if any
+
+use std::env;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process;
+
+use command::{Arguments, Command};
+use site::Site;
+
+static USAGE: &str = "
+Usage:
+    diecast {command} [<args>...]
+
+This runs an external command found on PATH.
+";
+
+pub struct External {
+    program: PathBuf,
+}
+
+impl External {
+    pub fn new(program: PathBuf) -> External {
+        External {
+            program,
+        }
+    }
+}
+
+/// Find the program implementing an external command
+/// in the given search path.
+pub fn find(name: &str, path: &OsStr) -> Option<PathBuf> {
+    let program = format!("diecast-{}{}", name, env::consts::EXE_SUFFIX);
+
+    env::split_paths(path)
+        .map(|dir| dir.join(&program))
+        .find(|candidate| candidate.is_file())
+}
+
+impl Command for External {
+    fn description(&self) -> &'static str {
+        "Run an external command"
+    }
+
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        // flags can't be interpreted on the program's behalf,
+        // so only the configuration file and environment apply
+        site.configuration_mut().load(None)?;
+
+        let configuration = site.configuration();
+
+        let mut child = process::Command::new(&self.program);
+
+        child
+            .args(arguments.argv.iter().skip(2))
+            .env("DIECAST_INPUT", &configuration.input)
+            .env("DIECAST_OUTPUT", &configuration.output)
+            .env("DIECAST_CACHE", &configuration.cache);
+
+        if let Some(ref file) = configuration.file {
+            child.env("DIECAST_CONFIG", file);
+        }
+
+        let status = child.status()?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(::Error::handler(
+                format!("`{}` failed: {}", self.program.display(), status)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, File};
+
+    use support::TempDir;
+    use super::find;
+
+    #[test]
+    fn find_on_path() {
+        let root = TempDir::new("external").unwrap();
+        let first = root.path().join("first");
+        let second = root.path().join("second");
+
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+
+        let program = format!("diecast-publish{}", env::consts::EXE_SUFFIX);
+        File::create(second.join(&program)).unwrap();
+
+        let path = env::join_paths(vec![&first, &second]).unwrap();
+
+        assert_eq!(find("publish", &path), Some(second.join(&program)));
+        assert_eq!(find("missing", &path), None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use command::{Arguments, Command};
+use configuration::Configuration;
+use site::Site;
+
+#[derive(Deserialize, Debug)]
+struct Options {
+    flag_format: String,
+    flag_config: Option<String>,
+}
+
+static USAGE: &str = "
+Usage:
+    diecast {command} [options]
+
+Options:
+    -h, --help            Print this message
+    -f FMT, --format FMT  Either dot or json [default: dot]
+    -c FILE, --config FILE
+                          Use this configuration file
+
+This prints the rule dependency graph, annotated with the
+number of items and the duration of each rule as of the
+last build that recorded them, i.e. the last incremental
+build or build with --statistics.
+";
+
+pub struct Graph {
+    format: String,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            format: String::from("dot"),
+        }
+    }
+
+    pub fn configure(&mut self, configuration: &mut Configuration, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
+
+        configuration.load(options.flag_config.as_ref().map(Path::new))?;
+
+        self.format = options.flag_format;
+
+        Ok(())
+    }
+}
+
+impl Default for Graph {
+    fn default() -> Graph {
+        Graph::new()
+    }
+}
+
+impl Command for Graph {
+    fn description(&self) -> &'static str {
+        "Print the rule dependency graph"
+    }
+
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site.configuration_mut(), arguments)?;
+
+        let graph = site.rule_graph()?;
+
+        match &self.format[..] {
+            "dot" => print!("{}", graph.to_dot()),
+            "json" => println!("{}", graph.to_json()),
+            format => {
+                return Err(::Error::Configuration(
+                    format!("unknown graph format `{}`, expected dot or json", format)));
+            },
+        }
+
+        Ok(())
+    }
+}
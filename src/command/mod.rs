@@ -1,20 +1,36 @@
+use std::cmp;
 use std::collections::HashMap;
+use std::env;
 use std::error::Error;
 
 use docopt::{self, Docopt};
+use serde::de::DeserializeOwned;
 
 use site::Site;
 
 pub mod build;
+pub mod check;
 pub mod clean;
 pub mod deploy;
+pub mod watch;
+pub mod serve;
+pub mod config;
+pub mod graph;
+pub mod external;
 
 pub trait Command {
     // TODO
     // not sure that it should have a description method
     // this should probably be provided separately?
     fn description(&self) -> &'static str;
-    fn run(&mut self, site: &mut Site) -> ::Result<()>;
+
+    /// The docopt usage message.
+    ///
+    /// `{command}` stands in for the name that the command
+    /// is registered under.
+    fn usage(&self) -> &'static str;
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()>;
 }
 
 impl<C> Command for Box<C>
@@ -23,8 +39,57 @@ where C: Command {
         (**self).description()
     }
 
-    fn run(&mut self, site: &mut Site) -> ::Result<()> {
-        (**self).run(site)
+    fn usage(&self) -> &'static str {
+        (**self).usage()
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        (**self).run(site, arguments)
+    }
+}
+
+/// The arguments that a command was invoked with.
+#[derive(Clone, Debug)]
+pub struct Arguments {
+    /// The name the command is registered under
+    pub name: String,
+
+    /// The arguments, starting with the program name
+    /// followed by the command name
+    pub argv: Vec<String>,
+}
+
+impl Arguments {
+    /// Fill in the command name in a usage message.
+    pub fn usage(&self, usage: &str) -> String {
+        usage.replace("{command}", &self.name)
+    }
+
+    /// Parse the arguments with a docopt usage message.
+    ///
+    /// This exits the process with the usage message if the
+    /// arguments don't match it or if help was requested.
+    pub fn parse<T>(&self, usage: &str) -> T
+    where T: DeserializeOwned {
+        Docopt::new(self.usage(usage))
+            .and_then(|d| d.argv(self.argv.iter()).help(true).deserialize())
+            .unwrap_or_else(|e| e.exit())
+    }
+}
+
+/// The command that was selected from the command line.
+pub struct Invocation {
+    command: Box<dyn Command>,
+    arguments: Arguments,
+}
+
+impl Invocation {
+    pub fn arguments(&self) -> &Arguments {
+        &self.arguments
+    }
+
+    pub fn run(&mut self, site: &mut Site) -> ::Result<()> {
+        self.command.run(site, &self.arguments)
     }
 }
 
@@ -34,6 +99,7 @@ struct Options {
     arg_args: Vec<String>,
 }
 
+#[allow(clippy::redundant_static_lifetimes)]
 static USAGE: &'static str = "
 Usage:
     diecast <command> [<args>...]
@@ -46,6 +112,8 @@ Options:
 Possible commands include:
 ";
 
+static HELP: &str = "Print the usage of a command";
+
 pub fn version() -> String {
     format!("diecast {}", match option_env!("CFG_VERSION") {
         Some(s) => String::from(s),
@@ -58,18 +126,25 @@ pub fn version() -> String {
 }
 
 pub struct Builder {
+    #[allow(bare_trait_objects)]
     commands: HashMap<String, Box<Command>>,
 }
 
 impl Builder {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Builder {
         let builder = Builder {
             commands: HashMap::new(),
         };
 
         builder
-            .command("build", build::Build)
+            .command("build", build::Build::new())
+            .command("check", check::Check)
             .command("clean", clean::Clean)
+            .command("watch", watch::Watch::new())
+            .command("serve", serve::Serve::new())
+            .command("config", config::Config)
+            .command("graph", graph::Graph::new())
     }
 
     pub fn command<S, C>(mut self, name: S, command: C) -> Builder
@@ -78,84 +153,167 @@ impl Builder {
         self
     }
 
-    pub fn build(mut self) -> Result<Box<Command>, Box<Error>> {
+    /// The top-level usage message, listing every registered command.
+    fn usage(&self) -> String {
         let mut usage = String::from(USAGE);
 
-        {
-            let mut cmds =
-                self.commands.iter()
-                .collect::<Vec<(&String, &Box<Command>)>>();
+        let mut cmds =
+            self.commands.iter()
+            .map(|(name, command)| (&name[..], command.description()))
+            .collect::<Vec<(&str, &str)>>();
 
-            cmds.sort_by(|a, b| a.0.cmp(b.0));
+        if !self.commands.contains_key("help") {
+            cmds.push(("help", HELP));
+        }
 
-            for &(k, v) in &cmds {
-                usage.push_str("    ");
-                usage.push_str(&k);
+        cmds.sort_by(|a, b| a.0.cmp(b.0));
 
-                // TODO: proper padding
-                if k.len() > 11 {
-                    panic!("the command name should be less than 12 characters");
-                }
+        // align the descriptions past the longest name
+        let width =
+            cmds.iter()
+            .map(|&(name, _)| name.len() + 1)
+            .fold(12, cmp::max);
 
-                let pad = 12 - k.len();
-                usage.push_str(&::std::iter::repeat(' ').take(pad).collect::<String>());
-                usage.push_str(&v.description());
-                usage.push('\n');
-            }
+        for &(name, description) in &cmds {
+            usage.push_str(&format!("    {:width$}{}\n", name, description, width = width));
         }
 
+        usage
+    }
+
+    pub fn build(self) -> Result<Invocation, Box<dyn Error>> {
+        self.build_from(env::args().collect())
+    }
+
+    /// Select a command based on the given arguments,
+    /// starting with the program name.
+    pub fn build_from(mut self, argv: Vec<String>) -> Result<Invocation, Box<dyn Error>> {
+        let usage = self.usage();
+
         let options: Options =
             Docopt::new(usage.clone())
                 .and_then(|d| {
                     d.options_first(true)
+                        .argv(argv.iter())
                         .help(true)
                         .version(Some(version()))
-                        .decode()
+                        .deserialize()
                 })?;
 
-        let cmd = options.arg_command.unwrap();
+        let cmd = options.arg_command.unwrap_or_default();
 
         let err =
             Err(From::from(docopt::Error::WithProgramUsage(
                 Box::new(docopt::Error::Help),
-                String::from(usage))));
-
-        // TODO
-        // the way this was changed prevents any commands from being passed
-        // to the sub-command. what needs to happen is that the flags need to
-        // be passed onto the sub-command once it is chosen
-        //
-        // Use Docopt.argv() to explicitly pass the _full_ arguments including
-        // the binary name.
-        //
-        // construct these args here, same as cargo is doing here:
-        // https://github.com/rust-lang/cargo/blob/master/src/bin/cargo.rs#L141
-        //
-        // UPDATE
-        // false alarm? The command runs Docopt itself on env::args() so
-        // everything seems to be working fine, it's just that the command has
-        // to ensure that Docopt is run and its effects take place
-        //
-        // that said, perhaps it _is_ beneficial to explicitly pass the argv to
-        // the command, so that the root command can support `diecast help subcommand`,
-        // which ends up rewriting the argv to [diecast, subcommand, -h]
-        //
-        // that may also be necessary to support external diecast-cmd binaries
-        // in PATH?
-        let command: Box<Command> = match &cmd[..] {
-            "" | "help" if options.arg_args.is_empty() => return err,
-            cmd => {
-                if let Some(command) = self.commands.remove(cmd) {
-                    command
-                } else {
-                    // here look in PATH to find program named diecast-$cmd
-                    // if not found, then output this message:
-                    println!("unknown command `{}`", cmd);
-                    return err;
+                usage)));
+
+        let program = argv.first().cloned().unwrap_or_else(|| String::from("diecast"));
+
+        // `help <command>` is rewritten to `<command> --help` so
+        // that the command prints its own usage
+        let (name, argv) =
+            if cmd == "help" && !self.commands.contains_key("help") {
+                match options.arg_args.first() {
+                    Some(name) => {
+                        (name.clone(), vec![program, name.clone(), String::from("--help")])
+                    },
+                    None => return err,
+                }
+            } else if cmd.is_empty() {
+                return err;
+            } else {
+                let mut full = vec![program, cmd.clone()];
+                full.extend(options.arg_args.iter().cloned());
+                (cmd, full)
+            };
+
+        let command: Box<dyn Command> = match self.commands.remove(&name) {
+            Some(command) => command,
+            None => {
+                let external =
+                    env::var_os("PATH")
+                    .and_then(|path| external::find(&name, &path));
+
+                match external {
+                    Some(program) => Box::new(external::External::new(program)),
+                    None => {
+                        println!("unknown command `{}`", name);
+                        return err;
+                    },
                 }
             },
         };
 
-        Ok(command)
+        Ok(Invocation {
+            command,
+            arguments: Arguments {
+                name,
+                argv,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use site::Site;
+    use super::{Arguments, Builder, Command};
+
+    struct Noop;
+
+    impl Command for Noop {
+        fn description(&self) -> &'static str {
+            "Do nothing"
+        }
+
+        fn usage(&self) -> &'static str {
+            "Usage: diecast {command}"
+        }
+
+        fn run(&mut self, _site: &mut Site, _arguments: &Arguments) -> ::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| String::from(*s)).collect()
+    }
+
+    #[test]
+    fn align_long_names() {
+        let usage =
+            Builder::new()
+            .command("a-rather-long-name", Noop)
+            .usage();
+
+        let column = |name: &str| {
+            let line = usage.lines().find(|l| l.trim_start().starts_with(name)).unwrap();
+            line.len() - line[4 + name.len() ..].trim_start().len()
+        };
+
+        assert_eq!(column("a-rather-long-name"), column("build"));
+        assert_eq!(column("build"), column("help"));
+    }
+
+    #[test]
+    fn pass_registered_name_and_argv() {
+        let invocation =
+            Builder::new()
+            .command("mess", Noop)
+            .build_from(argv(&["diecast", "mess", "-v"]))
+            .ok().unwrap();
+
+        assert_eq!(invocation.arguments().name, "mess");
+        assert_eq!(invocation.arguments().argv, argv(&["diecast", "mess", "-v"]));
+        assert_eq!(invocation.arguments().usage(Noop.usage()), "Usage: diecast mess");
+
+        let help =
+            Builder::new()
+            .command("mess", Noop)
+            .build_from(argv(&["diecast", "help", "mess"]))
+            .ok().unwrap();
+
+        assert_eq!(help.arguments().name, "mess");
+        assert_eq!(help.arguments().argv, argv(&["diecast", "mess", "--help"]));
     }
 }
@@ -0,0 +1,441 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use site::Site;
+use command::{Arguments, Command};
+use configuration::Configuration;
+use job::pool::Pool;
+
+#[derive(Deserialize, Debug)]
+struct Options {
+    flag_jobs: Option<usize>,
+    flag_verbose: bool,
+    flag_config: Option<String>,
+    flag_address: String,
+    flag_port: u16,
+}
+
+static USAGE: &str = "
+Usage:
+    diecast {command} [options]
+
+Options:
+    -h, --help            Print this message
+    -j N, --jobs N        Number of jobs to run in parallel
+    -v, --verbose         Use verbose output
+    -c FILE, --config FILE
+                          Use this configuration file
+    -a ADDR, --address ADDR
+                          Address to listen on [default: 127.0.0.1]
+    -p PORT, --port PORT  Port to listen on [default: 3000]
+
+This builds the site in preview mode and serves the output
+directory over HTTP. Requests for a directory are served its
+index.html, and requests for missing files are served the
+output directory's 404.html, if any. Requests are handled by
+as many threads as there are jobs.
+";
+
+/// How long to wait on a client before giving up on its request,
+/// so that an idle connection doesn't tie up a worker.
+const TIMEOUT: u64 = 10;
+
+/// The page served for missing files if the site doesn't have one.
+static NOT_FOUND: &str = "<!DOCTYPE html>
+<html>
+<head><title>404 Not Found</title></head>
+<body><h1>404 Not Found</h1></body>
+</html>
+";
+
+pub struct Serve {
+    address: String,
+    port: u16,
+}
+
+impl Serve {
+    pub fn new() -> Serve {
+        Serve {
+            address: String::from("127.0.0.1"),
+            port: 3000,
+        }
+    }
+
+    pub fn configure(&mut self, configuration: &mut Configuration, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
+
+        configuration.load(options.flag_config.as_ref().map(Path::new))?;
+
+        if let Some(jobs) = options.flag_jobs {
+            configuration.threads = jobs;
+        }
+
+        if options.flag_verbose {
+            configuration.is_verbose = true;
+        }
+        configuration.is_preview = true;
+
+        self.address = options.flag_address;
+        self.port = options.flag_port;
+
+        Ok(())
+    }
+}
+
+impl Default for Serve {
+    fn default() -> Serve {
+        Serve::new()
+    }
+}
+
+impl Command for Serve {
+    fn description(&self) -> &'static str {
+        "Build and serve the site locally"
+    }
+
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site.configuration_mut(), arguments)?;
+        site.build()?;
+
+        let root = Arc::new(site.configuration().output.clone());
+        let verbose = site.configuration().is_verbose;
+        let pool = Pool::new(site.configuration().threads);
+
+        let listener = TcpListener::bind((&self.address[..], self.port))?;
+
+        println!("serving {:?} at http://{}:{}/", root, self.address, self.port);
+
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    println!("could not accept connection: {}", e);
+                    continue;
+                },
+            };
+
+            let root = root.clone();
+
+            pool.execute(move || {
+                if let Err(e) = respond(&root, stream, verbose) {
+                    println!("could not respond to request: {}", e);
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The MIME type of a file, based on its extension.
+fn mime_type(path: &Path) -> &'static str {
+    let extension =
+        path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    match &extension[..] {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "rss" => "application/rss+xml",
+        "atom" => "application/atom+xml",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "eot" => "application/vnd.ms-fontobject",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Decode the percent-encoded octets in a request path.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            let hex = ::std::str::from_utf8(&bytes[idx + 1 .. idx + 3]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            if let Some(octet) = hex {
+                decoded.push(octet);
+                idx += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[idx]);
+        idx += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Map a request path to a path relative to the output directory.
+///
+/// Returns `None` if the request path attempts to escape
+/// the output directory.
+fn resolve(request: &str) -> Option<PathBuf> {
+    let path = request.split(['?', '#']).next().unwrap_or("");
+    let decoded = percent_decode(path);
+
+    let mut relative = PathBuf::new();
+
+    for component in Path::new(&decoded).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(relative)
+}
+
+/// What to serve for a request target.
+#[derive(Debug, PartialEq)]
+enum Lookup {
+    /// The file at this path.
+    File(PathBuf),
+    /// A redirect to this location.
+    Redirect(String),
+    /// The 404 page.
+    NotFound,
+}
+
+/// Find what to serve for a request target within the output directory.
+fn lookup(root: &Path, target: &str) -> Lookup {
+    let relative = match resolve(target) {
+        Some(relative) => relative,
+        None => return Lookup::NotFound,
+    };
+
+    let mut path = root.join(&relative);
+
+    if path.is_dir() {
+        // have relative links in the index resolve against the directory
+        let bare = target.split(['?', '#']).next().unwrap_or("");
+
+        if !bare.ends_with('/') {
+            return Lookup::Redirect(format!("{}/", bare));
+        }
+
+        path.push("index.html");
+    }
+
+    if path.is_file() {
+        Lookup::File(path)
+    } else {
+        Lookup::NotFound
+    }
+}
+
+/// Serve a single request.
+fn respond(root: &Path, stream: TcpStream, verbose: bool) -> io::Result<()> {
+    let timeout = Some(Duration::from_secs(TIMEOUT));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // drain the headers
+    loop {
+        let mut header = String::new();
+
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+
+    if verbose {
+        println!("{} {}", method, target);
+    }
+
+    if method != "GET" && method != "HEAD" {
+        return send(&mut stream, "405 Method Not Allowed",
+                    "text/plain; charset=utf-8", &[("Allow", "GET, HEAD")],
+                    b"method not allowed\n", false);
+    }
+
+    let head = method == "HEAD";
+
+    let path = match lookup(root, target) {
+        Lookup::File(path) => path,
+        Lookup::Redirect(location) => {
+            return send(&mut stream, "301 Moved Permanently",
+                        "text/plain; charset=utf-8", &[("Location", &location)],
+                        b"", head);
+        },
+        Lookup::NotFound => return not_found(root, &mut stream, head),
+    };
+
+    match read(&path) {
+        Ok(body) => send(&mut stream, "200 OK", mime_type(&path), &[], &body, head),
+        Err(_) => not_found(root, &mut stream, head),
+    }
+}
+
+/// Serve the site's 404 page, or a default one if it has none.
+fn not_found(root: &Path, stream: &mut TcpStream, head: bool) -> io::Result<()> {
+    let body = read(&root.join("404.html"))
+        .unwrap_or_else(|_| NOT_FOUND.as_bytes().to_vec());
+
+    send(stream, "404 Not Found", "text/html; charset=utf-8", &[], &body, head)
+}
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    File::open(path)?.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+fn send(stream: &mut TcpStream,
+        status: &str,
+        content_type: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        head: bool)
+-> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\n", status)?;
+    write!(stream, "Content-Type: {}\r\n", content_type)?;
+    write!(stream, "Content-Length: {}\r\n", body.len())?;
+    write!(stream, "Cache-Control: no-cache\r\n")?;
+    write!(stream, "Connection: close\r\n")?;
+
+    for &(name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+
+    write!(stream, "\r\n")?;
+
+    if !head {
+        stream.write_all(body)?;
+    }
+
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+
+    use support::TempDir;
+    use super::{Lookup, lookup, mime_type, percent_decode, resolve};
+
+    #[test]
+    fn decode_percent_encoding() {
+        assert_eq!(percent_decode("/a%20b.html"), "/a b.html");
+        assert_eq!(percent_decode("/%2e%2E%2Fa"), "/../a");
+        assert_eq!(percent_decode("/caf%C3%A9"), "/caf\u{e9}");
+
+        // malformed escapes are left as they are
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/%zz%4"), "/%zz%4");
+    }
+
+    #[test]
+    fn resolve_within_output() {
+        assert_eq!(resolve("/"), Some(PathBuf::new()));
+        assert_eq!(resolve("/posts/a.html"), Some(PathBuf::from("posts/a.html")));
+        assert_eq!(resolve("/./posts//a.html"), Some(PathBuf::from("posts/a.html")));
+        assert_eq!(resolve("/a%20b.html"), Some(PathBuf::from("a b.html")));
+
+        // encoded slashes separate components like literal ones
+        assert_eq!(resolve("/posts%2Fa.html"), Some(PathBuf::from("posts/a.html")));
+
+        // the query and fragment aren't part of the path
+        assert_eq!(resolve("/a.html?v=1#top"), Some(PathBuf::from("a.html")));
+        assert_eq!(resolve("/a.html#top?v=1"), Some(PathBuf::from("a.html")));
+        assert_eq!(resolve("/?../../etc/passwd"), Some(PathBuf::new()));
+    }
+
+    #[test]
+    fn reject_escapes() {
+        assert_eq!(resolve("/../etc/passwd"), None);
+        assert_eq!(resolve("/posts/../../etc/passwd"), None);
+        assert_eq!(resolve("/%2e%2e/etc/passwd"), None);
+        assert_eq!(resolve("/%2E%2E/etc/passwd"), None);
+        assert_eq!(resolve("/.%2e/etc/passwd"), None);
+        assert_eq!(resolve("/%2e%2e%2fetc%2fpasswd"), None);
+        assert_eq!(resolve("/posts%2F..%2F..%2Fetc"), None);
+    }
+
+    #[test]
+    fn lookup_files() {
+        let root = TempDir::new("serve").unwrap();
+        let root = root.path();
+
+        fs::create_dir_all(root.join("posts")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        File::create(root.join("index.html")).unwrap();
+        File::create(root.join("posts/index.html")).unwrap();
+        File::create(root.join("posts/a.html")).unwrap();
+
+        let file = |path: &str| Lookup::File(root.join(path));
+
+        assert_eq!(lookup(root, "/"), file("index.html"));
+        assert_eq!(lookup(root, "/posts/"), file("posts/index.html"));
+        assert_eq!(lookup(root, "/posts/?page=2"), file("posts/index.html"));
+        assert_eq!(lookup(root, "/posts/a.html"), file("posts/a.html"));
+
+        // directories are redirected to have a trailing slash
+        assert_eq!(lookup(root, "/posts"), Lookup::Redirect(String::from("/posts/")));
+        assert_eq!(lookup(root, "/posts?page=2"), Lookup::Redirect(String::from("/posts/")));
+
+        assert_eq!(lookup(root, "/missing.html"), Lookup::NotFound);
+        assert_eq!(lookup(root, "/empty/"), Lookup::NotFound);
+        assert_eq!(lookup(root, "/posts/../../index.html"), Lookup::NotFound);
+    }
+
+    #[test]
+    fn lookup_mime_types() {
+        let mime = |path: &str| mime_type(Path::new(path));
+
+        assert_eq!(mime("index.html"), "text/html; charset=utf-8");
+        assert_eq!(mime("INDEX.HTM"), "text/html; charset=utf-8");
+        assert_eq!(mime("style.css"), "text/css; charset=utf-8");
+        assert_eq!(mime("feed.atom"), "application/atom+xml");
+        assert_eq!(mime("logo.SVG"), "image/svg+xml");
+        assert_eq!(mime("font.woff2"), "font/woff2");
+        assert_eq!(mime("archive.tar.gz"), "application/octet-stream");
+        assert_eq!(mime("README"), "application/octet-stream");
+        assert_eq!(mime(".html"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cache;
+use job;
+use site::Site;
+use command::{Arguments, Command};
+use configuration::Configuration;
+
+#[derive(Deserialize, Debug)]
+struct Options {
+    flag_jobs: Option<usize>,
+    flag_verbose: bool,
+    flag_config: Option<String>,
+    flag_interval: u64,
+    flag_debounce: u64,
+}
+
+static USAGE: &str = "
+Usage:
+    diecast {command} [options]
+
+Options:
+    -h, --help            Print this message
+    -j N, --jobs N        Number of jobs to run in parallel
+    -v, --verbose         Use verbose output
+    -c FILE, --config FILE
+                          Use this configuration file
+    --interval MS         Milliseconds between scans of the input directory [default: 500]
+    --debounce MS         Milliseconds to wait for changes to settle [default: 200]
+
+This builds the site and then rebuilds the affected rules
+whenever files in the input directory change. The output of
+a removed file is removed as well once its rule is rebuilt.
+";
+
+pub struct Watch {
+    interval: Duration,
+    debounce: Duration,
+}
+
+impl Watch {
+    pub fn new() -> Watch {
+        Watch {
+            interval: Duration::from_millis(500),
+            debounce: Duration::from_millis(200),
+        }
+    }
+
+    pub fn configure(&mut self, configuration: &mut Configuration, arguments: &Arguments) -> ::Result<()> {
+        let options: Options = arguments.parse(USAGE);
+
+        configuration.load(options.flag_config.as_ref().map(Path::new))?;
+
+        if let Some(jobs) = options.flag_jobs {
+            configuration.threads = jobs;
+        }
+
+        if options.flag_verbose {
+            configuration.is_verbose = true;
+        }
+
+        self.interval = Duration::from_millis(options.flag_interval);
+        self.debounce = Duration::from_millis(options.flag_debounce);
+
+        Ok(())
+    }
+}
+
+impl Default for Watch {
+    fn default() -> Watch {
+        Watch::new()
+    }
+}
+
+impl Command for Watch {
+    fn description(&self) -> &'static str {
+        "Rebuild the site on changes"
+    }
+
+    fn usage(&self) -> &'static str {
+        USAGE
+    }
+
+    fn run(&mut self, site: &mut Site, arguments: &Arguments) -> ::Result<()> {
+        self.configure(site.configuration_mut(), arguments)?;
+
+        // snapshot before building so that changes made
+        // during the initial build aren't missed
+        let mut watcher = Watcher::new(site.configuration());
+
+        if let Err(e) = site.build() {
+            println!("{}", e);
+        }
+
+        loop {
+            println!("watching {:?} for changes", site.configuration().input);
+
+            let changed =
+                watcher.wait(site.configuration(), self.interval, self.debounce)
+                .into_iter()
+                .collect::<Vec<PathBuf>>();
+
+            println!("detected changes in {} file(s)", changed.len());
+
+            if site.configuration().is_verbose {
+                for path in &changed {
+                    println!("  {}", path.display());
+                }
+            }
+
+            // a failed rebuild shouldn't stop the watch
+            if let Err(e) = site.rebuild(&changed) {
+                println!("{}", e);
+            }
+        }
+    }
+}
+
+/// Polls the input directory for changes.
+struct Watcher {
+    fingerprints: cache::Fingerprints,
+}
+
+impl Watcher {
+    fn new(configuration: &Configuration) -> Watcher {
+        Watcher {
+            fingerprints: Watcher::snapshot(configuration),
+        }
+    }
+
+    fn snapshot(configuration: &Configuration) -> cache::Fingerprints {
+        cache::fingerprint(&configuration.input, &job::enumerate(configuration))
+    }
+
+    /// The paths that were added, modified, or removed since
+    /// the last poll, relative to the input directory.
+    fn poll(&mut self, configuration: &Configuration) -> BTreeSet<PathBuf> {
+        let current = Watcher::snapshot(configuration);
+
+        let mut changed =
+            current.iter()
+            .filter(|&(path, print)| self.fingerprints.get(path) != Some(print))
+            .map(|(path, _)| PathBuf::from(path))
+            .collect::<BTreeSet<PathBuf>>();
+
+        changed.extend(
+            self.fingerprints.keys()
+            .filter(|path| !current.contains_key(*path))
+            .map(PathBuf::from));
+
+        self.fingerprints = current;
+
+        changed
+    }
+
+    /// Block until there are changes.
+    ///
+    /// Once a change is detected, changes keep being collected until
+    /// none have occurred for the debounce period, so that a burst of
+    /// changes, e.g. an editor saving several files, causes a single
+    /// rebuild.
+    fn wait(&mut self,
+            configuration: &Configuration,
+            interval: Duration,
+            debounce: Duration)
+    -> BTreeSet<PathBuf> {
+        let mut changed = BTreeSet::new();
+
+        while changed.is_empty() {
+            thread::sleep(interval);
+            changed = self.poll(configuration);
+        }
+
+        let mut settled = Instant::now();
+
+        while settled.elapsed() < debounce {
+            thread::sleep(debounce / 4);
+
+            let more = self.poll(configuration);
+
+            if !more.is_empty() {
+                changed.extend(more);
+                settled = Instant::now();
+            }
+        }
+
+        changed
+    }
+}
@@ -1,14 +1,48 @@
-use std::path::PathBuf;
-use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::fmt::{self, Write};
+use std::str::FromStr;
 use std::fs::File;
 use std::io::Read;
 use std::sync::Arc;
+use std::env;
 
 use num_cpus;
+use serde::de::DeserializeOwned;
+use serde_path_to_error;
 use toml;
-use regex::Regex;
 
-use pattern::Pattern;
+use pattern::{self, AnyOf, Pattern};
+
+/// What to do when items are routed to the same output path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Collisions {
+    /// Fail the rule whose item claimed the path last.
+    Error,
+
+    /// Report the collision and let the last writer win.
+    Warn,
+}
+
+impl FromStr for Collisions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Collisions, String> {
+        match s {
+            "error" => Ok(Collisions::Error),
+            "warn" => Ok(Collisions::Warn),
+            _ => Err(String::from("must be either `error` or `warn`")),
+        }
+    }
+}
+
+impl fmt::Display for Collisions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Collisions::Error => f.write_str("error"),
+            Collisions::Warn => f.write_str("warn"),
+        }
+    }
+}
 
 // TODO: audit
 
@@ -18,15 +52,25 @@ use pattern::Pattern;
 pub struct Configuration {
     toml: toml::Value,
 
+    /// The configuration file that was loaded, if any
+    pub file: Option<PathBuf>,
+
+    /// The textual form of `ignore`, if it was configured
+    /// from the configuration file or the environment
+    ignore_source: Option<toml::Value>,
+
+    /// The textual form of `keep`, if it was configured
+    /// from the configuration file or the environment
+    keep_source: Option<toml::Value>,
+
     /// The input directory
     pub input: PathBuf,
 
     /// The output directory
     pub output: PathBuf,
 
-    // TODO: necessary?
-    // The cache directory
-    // cache: PathBuf,
+    /// The cache directory
+    pub cache: PathBuf,
 
     /// The root command that was invoked
     pub command: String,
@@ -42,87 +86,417 @@ pub struct Configuration {
     /// the following are from hakyll
     /// e.g.
     /// config.ignore = regex!("^\.|^#|~$|\.swp$")
+    ///
+    /// in the configuration file, it's either a pattern as understood
+    /// by `pattern::parse` or a list of them, any of which may match
+    /// e.g.
+    /// ignore = "re:^\\. | *.swp"
+    /// ignore = ["re:^\\.", "*.swp", "*~"]
+    #[allow(bare_trait_objects)]
     pub ignore: Option<Arc<Pattern + Sync + Send>>,
 
+    /// Whether to honor `.gitignore` files within the input directory
+    /// in addition to `.diecastignore` files
+    pub use_gitignore: bool,
+
     /// Whether we're in preview mode
+    ///
+    /// This is set by the `serve` command. Handlers can consult it
+    /// to e.g. include drafts when previewing the site locally.
     pub is_preview: bool,
 
+    /// Whether to only re-run the rules affected by changes
+    /// since the previous build, as recorded in the cache
+    ///
+    /// The rules that the affected rules depend on are re-run in full
+    /// as well, since their binds aren't cached.
+    pub is_incremental: bool,
+
+    /// Whether to record how each rule fared in the cache directory,
+    /// e.g. for the `graph` command
+    ///
+    /// Incremental builds always record it.
+    pub is_recording_statistics: bool,
+
+    /// Whether to keep building after a failure
+    ///
+    /// Every rule and item that doesn't depend on a failed rule is still
+    /// processed, and the failures are reported together at the end.
+    pub is_keep_going: bool,
+
+    /// Whether to build into the output directory as it is instead of
+    /// cleaning it out first
+    ///
+    /// The files that no rule wrote are removed once the build succeeds,
+    /// so the previous output remains available throughout the build.
+    pub is_pruning: bool,
+
+    /// Whether to build into a staging directory beside the output
+    /// directory, which replaces it only if the build succeeds
+    ///
+    /// The previous output is kept as a backup beside it.
+    pub is_atomic: bool,
+
+    /// What to do when items are routed to the same output path
+    pub collisions: Collisions,
+
     // TODO
     // should this just be implicit in the ignore field?
     // e.g. ^\.
     /// Whether to ignore hidden files and directories at the
     /// top level of the output directory when cleaning it out
     pub ignore_hidden: bool,
-}
 
-// TODO configuration hierarchy
-// CLI -> toml -> code -> defaults
-impl Configuration {
-    pub fn new() -> Configuration {
-        // if there's no file just set an empty toml table
-        // otherwise forcibly attempt to read the contents and parsing them
-        // if either of those two fails the program should and will panic
-        let toml =
-            File::open("Diecast.toml")
-            .map(|mut file| {
-                let mut contents = String::new();
-                file.read_to_string(&mut contents).unwrap();
-                let parsed: toml::Value = contents.parse().unwrap();
-
-                parsed.as_table().expect("configuration must be a table!");
-
-                parsed
-            })
-            .unwrap_or(toml::Value::Table(BTreeMap::new()));
-
-        let ignore =
-            toml.get("diecast.ignore")
-            .and_then(toml::Value::as_str)
-            .and_then(|s| {
-                match Regex::new(s) {
-                    Ok(r) => Some(Arc::new(r) as Arc<Pattern + Send + Sync>),
-                    Err(e) => {
-                        panic!("could not parse regex: {}", e);
-                    },
-                }
-            });
+    /// a pattern of paths within the output directory that
+    /// are kept when cleaning it out
+    ///
+    /// the paths are relative to the output directory, and a
+    /// directory that's kept is kept along with its contents
+    /// e.g.
+    /// config.keep = regex!("^CNAME$|^\.git$")
+    ///
+    /// it's configured like `ignore` in the configuration file
+    pub keep: Option<Arc<dyn Pattern + Sync + Send>>,
+}
 
-        let input =
-            toml.get("diecast.input")
-            .and_then(toml::Value::as_str)
-            .map_or_else(|| PathBuf::from("input"), PathBuf::from);
+/// The name of the configuration file.
+pub static FILE_NAME: &str = "Diecast.toml";
 
-        let output =
-            toml.get("diecast.output")
-            .and_then(toml::Value::as_str)
-            .map_or_else(|| PathBuf::from("output"), PathBuf::from);
+/// The prefix of the environment variables that configure the build.
+static ENV_PREFIX: &str = "DIECAST_";
 
+/// The configuration hierarchy, in increasing order of precedence:
+///
+/// 1. defaults, from `Configuration::new`
+/// 2. code, by modifying the configuration before it's loaded
+/// 3. the `[diecast]` table of the configuration file
+/// 4. `DIECAST_*` environment variables
+/// 5. command-line flags, applied by the command after loading
+///
+/// The configuration file can be given explicitly, e.g. with `--config`,
+/// or through the `DIECAST_CONFIG` environment variable. Otherwise it's
+/// the first `Diecast.toml` found by walking up from the current
+/// directory. Relative paths in the configuration file are relative
+/// to the directory containing it.
+impl Configuration {
+    /// The default configuration.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Configuration {
         Configuration {
-            toml: toml,
+            toml: toml::Value::Table(toml::value::Table::new()),
+            file: None,
+            ignore_source: None,
+            keep_source: None,
             // TODO: setting it to error by default seems like a wart
-            input: input,
-            output: output,
+            input: PathBuf::from("input"),
+            output: PathBuf::from("output"),
+            cache: PathBuf::from("cache"),
             command: String::new(),
             threads: num_cpus::get(),
             is_verbose: false,
-            ignore: ignore,
+            ignore: None,
             is_preview: false,
+            is_incremental: false,
+            is_recording_statistics: false,
+            is_keep_going: false,
+            is_pruning: false,
+            is_atomic: false,
+            collisions: Collisions::Error,
+            use_gitignore: false,
             ignore_hidden: false,
+            keep: None,
         }
     }
 
+    /// Find the configuration file by walking up from the given directory.
+    pub fn locate(directory: &Path) -> Option<PathBuf> {
+        directory.ancestors()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| path.is_file())
+    }
+
+    /// Layer the configuration file and the environment over
+    /// this configuration.
+    ///
+    /// If `file` is `None`, the file is taken from `DIECAST_CONFIG`
+    /// or located from the current directory, and it's fine if there's
+    /// none. A file that was given explicitly must exist.
+    pub fn load(&mut self, file: Option<&Path>) -> ::Result<()> {
+        let file = match file {
+            Some(file) => Some(file.to_path_buf()),
+            None => {
+                match env::var_os("DIECAST_CONFIG") {
+                    Some(file) => Some(PathBuf::from(file)),
+                    None => Configuration::locate(&env::current_dir()?),
+                }
+            },
+        };
+
+        if let Some(file) = file {
+            self.merge_file(&file)?;
+        }
+
+        self.merge_env(env::vars())
+    }
+
+    /// Layer a configuration file over this configuration.
+    pub fn merge_file(&mut self, path: &Path) -> ::Result<()> {
+        let mut contents = String::new();
+
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| {
+                ::Error::Configuration(
+                    format!("could not read the configuration file {:?}: {}", path, e))
+            })?;
+
+        let parsed: toml::Value =
+            toml::from_str(&contents)
+            .map_err(|e| {
+                ::Error::Configuration(
+                    format!("could not parse the configuration file {:?}: {}", path, e))
+            })?;
+
+        self.merge_toml(parsed, path)?;
+        self.file = Some(path.to_path_buf());
+
+        Ok(())
+    }
+
+    /// Layer the `[diecast]` table of a parsed configuration file
+    /// over this configuration.
+    ///
+    /// The other tables are kept so that they can be accessed by rules.
+    pub fn merge_toml(&mut self, toml: toml::Value, path: &Path) -> ::Result<()> {
+        let error = |message: String| {
+            ::Error::Configuration(format!("{} in {:?}", message, path))
+        };
+
+        // relative paths are relative to the configuration file
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        {
+            let table = match toml.get("diecast") {
+                Some(toml::Value::Table(table)) => Some(table),
+                Some(_) => return Err(error(String::from("`diecast` must be a table"))),
+                None => None,
+            };
+
+            for (key, value) in table.into_iter().flat_map(|table| table.iter()) {
+                let string = || {
+                    value.as_str()
+                    .ok_or_else(|| error(format!("`diecast.{}` must be a string", key)))
+                };
+
+                let boolean = || {
+                    value.as_bool()
+                    .ok_or_else(|| error(format!("`diecast.{}` must be a boolean", key)))
+                };
+
+                match &key[..] {
+                    "input" => self.input = base.join(string()?),
+                    "output" => self.output = base.join(string()?),
+                    "cache" => self.cache = base.join(string()?),
+                    "ignore" => {
+                        self.set_ignore(value.clone())
+                            .map_err(|e| error(format!("`diecast.ignore` {}", e)))?;
+                    },
+                    "jobs" => {
+                        self.threads =
+                            value.as_integer()
+                            .and_then(|jobs| if jobs > 0 { Some(jobs as usize) } else { None })
+                            .ok_or_else(|| {
+                                error(String::from("`diecast.jobs` must be a positive integer"))
+                            })?;
+                    },
+                    "verbose" => self.is_verbose = boolean()?,
+                    "gitignore" => self.use_gitignore = boolean()?,
+                    "ignore_hidden" => self.ignore_hidden = boolean()?,
+                    "keep" => {
+                        self.set_keep(value.clone())
+                            .map_err(|e| error(format!("`diecast.keep` {}", e)))?;
+                    },
+                    "incremental" => self.is_incremental = boolean()?,
+                    "statistics" => self.is_recording_statistics = boolean()?,
+                    "keep_going" => self.is_keep_going = boolean()?,
+                    "prune" => self.is_pruning = boolean()?,
+                    "atomic" => self.is_atomic = boolean()?,
+                    "collisions" => {
+                        self.collisions =
+                            string()?.parse()
+                            .map_err(|e| error(format!("`diecast.collisions` {}", e)))?;
+                    },
+                    _ => return Err(error(format!("unknown key `diecast.{}`", key))),
+                }
+            }
+        }
+
+        if !toml.is_table() {
+            return Err(error(String::from("the configuration must be a table")));
+        }
+
+        self.toml = toml;
+
+        Ok(())
+    }
+
+    /// Layer the `DIECAST_*` environment variables over this configuration.
+    pub fn merge_env<I>(&mut self, vars: I) -> ::Result<()>
+    where I: IntoIterator<Item = (String, String)> {
+        for (name, value) in vars {
+            if !name.starts_with(ENV_PREFIX) {
+                continue;
+            }
+
+            let error = |expected: &str| {
+                ::Error::Configuration(
+                    format!("`{}` must be {}, not `{}`", name, expected, value))
+            };
+
+            let boolean = || {
+                match &value.to_lowercase()[..] {
+                    "1" | "true" | "yes" => Ok(true),
+                    "0" | "false" | "no" => Ok(false),
+                    _ => Err(error("a boolean")),
+                }
+            };
+
+            match &name[ENV_PREFIX.len() ..] {
+                "INPUT" => self.input = PathBuf::from(&value),
+                "OUTPUT" => self.output = PathBuf::from(&value),
+                "CACHE" => self.cache = PathBuf::from(&value),
+                "IGNORE" => {
+                    self.set_ignore(toml::Value::String(value.clone()))
+                        .map_err(|e| ::Error::Configuration(format!("`{}` {}", name, e)))?;
+                },
+                "JOBS" => {
+                    self.threads =
+                        value.parse::<usize>().ok()
+                        .and_then(|jobs| if jobs > 0 { Some(jobs) } else { None })
+                        .ok_or_else(|| error("a positive integer"))?;
+                },
+                "VERBOSE" => self.is_verbose = boolean()?,
+                "GITIGNORE" => self.use_gitignore = boolean()?,
+                "IGNORE_HIDDEN" => self.ignore_hidden = boolean()?,
+                "KEEP" => {
+                    self.set_keep(toml::Value::String(value.clone()))
+                        .map_err(|e| ::Error::Configuration(format!("`{}` {}", name, e)))?;
+                },
+                "INCREMENTAL" => self.is_incremental = boolean()?,
+                "STATISTICS" => self.is_recording_statistics = boolean()?,
+                "KEEP_GOING" => self.is_keep_going = boolean()?,
+                "PRUNE" => self.is_pruning = boolean()?,
+                "ATOMIC" => self.is_atomic = boolean()?,
+                "COLLISIONS" => {
+                    self.collisions =
+                        value.parse()
+                        .map_err(|e| ::Error::Configuration(format!("`{}` {}", name, e)))?;
+                },
+                // e.g. DIECAST_CONFIG, or variables meant for other tools
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the ignore pattern from its textual form.
+    fn set_ignore(&mut self, source: toml::Value) -> Result<(), String> {
+        self.ignore = Some(pattern(&source)?);
+        self.ignore_source = Some(source);
+
+        Ok(())
+    }
+
+    /// Set the keep pattern from its textual form.
+    fn set_keep(&mut self, source: toml::Value) -> Result<(), String> {
+        self.keep = Some(pattern(&source)?);
+        self.keep_source = Some(source);
+
+        Ok(())
+    }
+
+    /// The effective configuration as TOML.
+    ///
+    /// This includes the other tables of the configuration file.
+    pub fn to_toml(&self) -> String {
+        fn quote(s: &str) -> String {
+            toml::Value::String(String::from(s)).to_string()
+        }
+
+        fn path(p: &Path) -> String {
+            quote(&p.to_string_lossy())
+        }
+
+        let mut out = String::new();
+
+        match self.file {
+            Some(ref file) => writeln!(out, "# configuration file: {}", file.display()),
+            None => writeln!(out, "# no configuration file"),
+        }.unwrap();
+
+        writeln!(out, "[diecast]").unwrap();
+        writeln!(out, "input = {}", path(&self.input)).unwrap();
+        writeln!(out, "output = {}", path(&self.output)).unwrap();
+        writeln!(out, "cache = {}", path(&self.cache)).unwrap();
+
+        match (&self.ignore, &self.ignore_source) {
+            (&Some(_), Some(source)) => writeln!(out, "ignore = {}", source),
+            (&Some(_), &None) => writeln!(out, "# ignore is set in code"),
+            (&None, _) => Ok(()),
+        }.unwrap();
+
+        writeln!(out, "jobs = {}", self.threads).unwrap();
+        writeln!(out, "verbose = {}", self.is_verbose).unwrap();
+        writeln!(out, "gitignore = {}", self.use_gitignore).unwrap();
+        writeln!(out, "ignore_hidden = {}", self.ignore_hidden).unwrap();
+
+        match (&self.keep, &self.keep_source) {
+            (&Some(_), Some(source)) => writeln!(out, "keep = {}", source),
+            (&Some(_), &None) => writeln!(out, "# keep is set in code"),
+            (&None, _) => Ok(()),
+        }.unwrap();
+        writeln!(out, "incremental = {}", self.is_incremental).unwrap();
+        writeln!(out, "statistics = {}", self.is_recording_statistics).unwrap();
+        writeln!(out, "keep_going = {}", self.is_keep_going).unwrap();
+        writeln!(out, "prune = {}", self.is_pruning).unwrap();
+        writeln!(out, "atomic = {}", self.is_atomic).unwrap();
+        writeln!(out, "collisions = {}", quote(&self.collisions.to_string())).unwrap();
+
+        if let toml::Value::Table(ref table) = self.toml {
+            let mut others = table.clone();
+            others.remove("diecast");
+
+            if !others.is_empty() {
+                if let Ok(rest) = toml::to_string(&others) {
+                    writeln!(out, "\n{}", rest.trim_end()).unwrap();
+                }
+            }
+        }
+
+        out
+    }
+
+    #[allow(clippy::multiple_bound_locations, clippy::needless_maybe_sized)]
     pub fn input<P: ?Sized>(mut self, input: P) -> Configuration
     where P: Into<PathBuf> {
         self.input = input.into();
         self
     }
 
+    #[allow(clippy::multiple_bound_locations, clippy::needless_maybe_sized)]
     pub fn output<P: ?Sized>(mut self, output: P) -> Configuration
     where P: Into<PathBuf> {
         self.output = output.into();
         self
     }
 
+    pub fn cache<P>(mut self, cache: P) -> Configuration
+    where P: Into<PathBuf> {
+        self.cache = cache.into();
+        self
+    }
+
     pub fn toml(&self) -> &toml::Value {
         &self.toml
     }
@@ -135,6 +509,45 @@ impl Configuration {
         }
     }
 
+    /// Deserialize a section of the configuration file.
+    ///
+    /// This lets a handler declare the schema of its settings as a
+    /// serde type, e.g. `configuration.section::<FeedSettings>("feed")`
+    /// for a `[feed]` table. A missing section is treated as an empty
+    /// table, so that a type whose fields all have defaults can be
+    /// used without configuring anything.
+    ///
+    /// Errors name the configuration file, the section and the
+    /// offending key.
+    pub fn section<T>(&self, name: &str) -> ::Result<T>
+    where T: DeserializeOwned {
+        let file = match self.file {
+            Some(ref file) => format!("{:?}", file),
+            None => String::from("the configuration"),
+        };
+
+        let value = match self.toml.get(name) {
+            Some(toml::Value::Table(table)) => toml::Value::Table(table.clone()),
+            Some(_) => {
+                return Err(::Error::Configuration(
+                    format!("`{}` must be a table in {}", name, file)));
+            },
+            None => toml::Value::Table(toml::value::Table::new()),
+        };
+
+        serde_path_to_error::deserialize(value).map_err(|e| {
+            let reason = e.inner().message().trim().replace('\n', " ");
+
+            // the path of the key at fault, relative to the section
+            match e.path().to_string() {
+                ref path if path == "." => ::Error::Configuration(
+                    format!("invalid section `{}` in {}: {}", name, file, reason)),
+                path => ::Error::Configuration(
+                    format!("invalid `{}.{}` in {}: {}", name, path, file, reason)),
+            }
+        })
+    }
+
     pub fn thread_count(mut self, count: usize) -> Configuration {
         self.threads = count;
         self
@@ -143,6 +556,12 @@ impl Configuration {
     pub fn ignore<P>(mut self, pattern: P) -> Configuration
     where P: Pattern + Sync + Send + 'static {
         self.ignore = Some(Arc::new(pattern));
+        self.ignore_source = None;
+        self
+    }
+
+    pub fn use_gitignore(mut self, use_gitignore: bool) -> Configuration {
+        self.use_gitignore = use_gitignore;
         self
     }
 
@@ -151,9 +570,207 @@ impl Configuration {
         self
     }
 
+    pub fn keep<P>(mut self, pattern: P) -> Configuration
+    where P: Pattern + Sync + Send + 'static {
+        self.keep = Some(Arc::new(pattern));
+        self.keep_source = None;
+        self
+    }
+
     pub fn preview(mut self, is_preview: bool) -> Configuration {
         self.is_preview = is_preview;
         self
     }
+
+    pub fn incremental(mut self, is_incremental: bool) -> Configuration {
+        self.is_incremental = is_incremental;
+        self
+    }
+
+    pub fn statistics(mut self, is_recording_statistics: bool) -> Configuration {
+        self.is_recording_statistics = is_recording_statistics;
+        self
+    }
+
+    pub fn keep_going(mut self, is_keep_going: bool) -> Configuration {
+        self.is_keep_going = is_keep_going;
+        self
+    }
+
+    pub fn prune(mut self, is_pruning: bool) -> Configuration {
+        self.is_pruning = is_pruning;
+        self
+    }
+
+    pub fn atomic(mut self, is_atomic: bool) -> Configuration {
+        self.is_atomic = is_atomic;
+        self
+    }
+
+    pub fn collisions(mut self, collisions: Collisions) -> Configuration {
+        self.collisions = collisions;
+        self
+    }
+}
+
+
+/// Parse a pattern as understood by `pattern::parse`, or a
+/// list of them any of which may match.
+fn pattern(source: &toml::Value) -> Result<Arc<dyn Pattern + Sync + Send>, String> {
+    match *source {
+        toml::Value::String(ref source) => {
+            pattern::parse(source)
+                .map(Arc::from)
+                .map_err(|e| format!("has an {}", e))
+        },
+        toml::Value::Array(ref sources) => {
+            let patterns =
+                sources.iter()
+                .map(|source| {
+                    let source = source.as_str().ok_or("must only contain strings")?;
+                    pattern::parse(source).map_err(|e| format!("has an {}", e))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            Ok(Arc::new(AnyOf::from(patterns)))
+        },
+        _ => Err(String::from("must be a string or an array of strings")),
+    }
 }
 
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use toml;
+
+    use super::Configuration;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Feed {
+        title: String,
+        #[serde(default)]
+        entries: u32,
+        #[serde(default)]
+        authors: Vec<Author>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Author {
+        name: String,
+    }
+
+    fn parse(contents: &str) -> toml::Value {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn layer_file_over_code() {
+        let mut configuration =
+            Configuration::new()
+            .input("code-input")
+            .output("code-output");
+
+        let file = parse("[diecast]\noutput = \"site\"\njobs = 3\n\n[feed]\ntitle = \"blog\"\n");
+
+        configuration.merge_toml(file, Path::new("project/Diecast.toml")).unwrap();
+
+        assert_eq!(configuration.input, PathBuf::from("code-input"));
+        assert_eq!(configuration.output, PathBuf::from("project/site"));
+        assert_eq!(configuration.threads, 3);
+        assert!(configuration.toml().get("feed").is_some());
+    }
+
+    #[test]
+    fn layer_env_over_file() {
+        let mut configuration = Configuration::new();
+
+        let file = parse("[diecast]\ninput = \"posts\"\nkeep_going = false\n");
+        configuration.merge_toml(file, Path::new("Diecast.toml")).unwrap();
+
+        let vars = vec![
+            (String::from("DIECAST_INPUT"), String::from("drafts")),
+            (String::from("DIECAST_KEEP_GOING"), String::from("yes")),
+            (String::from("HOME"), String::from("/home")),
+        ];
+
+        configuration.merge_env(vars).unwrap();
+
+        assert_eq!(configuration.input, PathBuf::from("drafts"));
+        assert!(configuration.is_keep_going);
+    }
+
+    #[test]
+    fn reject_invalid_values() {
+        let mut configuration = Configuration::new();
+
+        let wrong_type = parse("[diecast]\ninput = 3\n");
+        let unknown = parse("[diecast]\ninptu = \"typo\"\n");
+        let bad_pattern = parse("[diecast]\nignore = \"re:(\"\n");
+        let bad_collisions = parse("[diecast]\ncollisions = \"ignore\"\n");
+        let bad_list = parse("[diecast]\nignore = [\"*.swp\", \"re:(\"]\n");
+
+        let path = Path::new("Diecast.toml");
+
+        assert!(configuration.merge_toml(wrong_type, path).is_err());
+        assert!(configuration.merge_toml(unknown, path).is_err());
+        assert!(configuration.merge_toml(bad_pattern, path).is_err());
+        assert!(configuration.merge_toml(bad_collisions, path).is_err());
+        assert!(configuration.merge_toml(bad_list, path).is_err());
+
+        let vars = vec![(String::from("DIECAST_JOBS"), String::from("zero"))];
+
+        assert!(configuration.merge_env(vars).is_err());
+    }
+
+    #[test]
+    fn parse_patterns() {
+        let path = Path::new("Diecast.toml");
+
+        let single = parse("[diecast]\nignore = \"*.swp | re:^\\\\.\"\n");
+        let list = parse("[diecast]\nignore = [\"*.swp\", \"re:^\\\\.\"]\n");
+
+        for file in [single, list] {
+            let mut configuration = Configuration::new();
+            configuration.merge_toml(file, path).unwrap();
+
+            let ignore = configuration.ignore.clone().unwrap();
+            assert!(ignore.matches(Path::new("post.md.swp")));
+            assert!(ignore.matches(Path::new(".hidden")));
+            assert!(!ignore.matches(Path::new("post.md")));
+        }
+
+        // a glob, rather than a regular expression matching anywhere
+        let mut configuration = Configuration::new();
+        let vars = vec![(String::from("DIECAST_KEEP"), String::from("CNAME"))];
+        configuration.merge_env(vars).unwrap();
+
+        let keep = configuration.keep.clone().unwrap();
+        assert!(keep.matches(Path::new("CNAME")));
+        assert!(!keep.matches(Path::new("CNAME.bak")));
+    }
+
+    #[test]
+    fn deserialize_sections() {
+        let mut configuration = Configuration::new();
+
+        let file = parse("[feed]\ntitle = \"blog\"\nentries = 10\n\n[broken]\ntitle = 3\n");
+        configuration.merge_toml(file, Path::new("Diecast.toml")).unwrap();
+
+        let feed: Feed = configuration.section("feed").unwrap();
+        assert_eq!(feed, Feed { title: String::from("blog"), entries: 10, authors: vec![] });
+
+        let error = configuration.section::<Feed>("broken").unwrap_err().to_string();
+        assert!(error.contains("`broken.title`"), "{}", error);
+
+        let error = configuration.section::<Feed>("missing").unwrap_err().to_string();
+        assert!(error.contains("`missing`") && error.contains("`title`"), "{}", error);
+
+        let file = parse("[nested]\ntitle = \"blog\"\n\n[[nested.authors]]\nname = \"a\"\n\n\
+                          [[nested.authors]]\nname = 3\n");
+        configuration.merge_toml(file, Path::new("Diecast.toml")).unwrap();
+
+        let error = configuration.section::<Feed>("nested").unwrap_err().to_string();
+        assert!(error.contains("invalid `nested.authors[1].name` in "), "{}", error);
+    }
+}
@@ -9,6 +9,7 @@ use std::collections::btree_map::Entry::Vacant;
 use std::borrow::Borrow;
 use std::hash::Hash;
 use std::any::Any;
+use std::cmp;
 use std::fmt;
 
 // TODO: just make Graph use usize? or String?
@@ -56,6 +57,7 @@ where T: Ord + Clone + Hash {
     }
 
     /// Register a dependency constraint.
+    #[allow(clippy::unwrap_or_default)]
     pub fn add_edge(&mut self, a: T, b: T) {
         self.edges.entry(a.clone())
             .or_insert(BTreeSet::new())
@@ -67,12 +69,14 @@ where T: Ord + Clone + Hash {
     }
 
     /// The nodes in the graph.
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn nodes(&self) -> Keys<T, BTreeSet<T>> {
         self.edges.keys()
     }
 
     // TODO: this seems identical to the above?
     /// The dependents a node has.
+    #[allow(clippy::multiple_bound_locations)]
     pub fn dependents_of<Q: ?Sized>(&self, node: &Q) -> Option<&BTreeSet<T>>
     where T: Borrow<Q>, Q: Ord {
         self.edges.get(node)
@@ -80,19 +84,99 @@ where T: Ord + Clone + Hash {
 
     // TODO: this and the above should just return an empty btreeset if no deps
     // can't cause it's a reference, argh
+    #[allow(clippy::multiple_bound_locations)]
     pub fn dependencies_of<Q: ?Sized>(&self, node: &Q) -> Option<&BTreeSet<T>>
     where T: Borrow<Q>, Q: Ord {
         self.reverse.get(node)
     }
 
-    /// The number of dependencies a node has.
-    pub fn dependency_count<Q: ?Sized>(&self, node: &Q) -> usize
-    where T: Borrow<Q>, Q: Ord {
-        self.reverse.get(node).map_or(0usize, |s| s.len())
+    /// The given nodes along with every node they transitively depend on.
+    pub fn dependencies_closure(&self, nodes: Vec<T>) -> BTreeSet<T> {
+        let mut closure = BTreeSet::new();
+        let mut stack = nodes;
+
+        while let Some(node) = stack.pop() {
+            if let Some(deps) = self.dependencies_of(&node) {
+                for dep in deps {
+                    if !closure.contains(dep) {
+                        stack.push(dep.clone());
+                    }
+                }
+            }
+
+            closure.insert(node);
+        }
+
+        closure
+    }
+
+    /// Every cycle in the graph.
+    ///
+    /// A cycle is reported for each set of nodes that transitively depend
+    /// on each other, i.e. each strongly connected component, so that
+    /// independent cycles are all reported at once rather than one at a
+    /// time. Each cycle starts and ends with its least node, and follows
+    /// the evaluation order, i.e. every node is a dependency of the next.
+    pub fn cycles(&self) -> Vec<CycleError<T>>
+    where T: fmt::Debug + fmt::Display + Any {
+        let mut components = Components::new(self);
+
+        for node in self.edges.keys() {
+            if !components.index.contains_key(node) {
+                components.connect(node.clone());
+            }
+        }
+
+        components.found.into_iter()
+            .filter(|component| {
+                let first = component.iter().next().unwrap();
+
+                component.len() > 1
+                || self.dependents_of(first).is_some_and(|d| d.contains(first))
+            })
+            .map(|component| CycleError { cycle: self.cycle_within(&component) })
+            .collect()
+    }
+
+    /// The shortest cycle through the least node of a strongly
+    /// connected component.
+    fn cycle_within(&self, component: &BTreeSet<T>) -> VecDeque<T> {
+        let start = component.iter().next().unwrap().clone();
+
+        let mut edge_to = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start.clone());
+
+        while let Some(node) = queue.pop_front() {
+            for neighbor in self.dependents_of(&node).into_iter().flat_map(|n| n.iter()) {
+                if *neighbor == start {
+                    let mut path = VecDeque::new();
+                    path.push_front(start.clone());
+
+                    let mut current = Some(&node);
+
+                    while let Some(found) = current {
+                        path.push_front(found.clone());
+                        current = edge_to.get(found);
+                    }
+
+                    return path;
+                }
+
+                if component.contains(neighbor) && !edge_to.contains_key(neighbor) {
+                    edge_to.insert(neighbor.clone(), node.clone());
+                    queue.push_back(neighbor.clone());
+                }
+            }
+        }
+
+        unreachable!("a strongly connected component must contain a cycle");
     }
 
     /// Topological ordering from a specific set of source nodes.
-    #[allow(dead_code)]
+    ///
+    /// The ordering contains the source nodes and every node
+    /// that transitively depends on them.
     pub fn resolve(&self, nodes: Vec<T>) -> Result<Order<T>, CycleError<T>>
     where T: fmt::Debug + fmt::Display + Any {
         Topological::new(self).from(nodes)
@@ -121,6 +205,14 @@ where T: fmt::Debug + fmt::Display + Any {
     cycle: VecDeque<T>,
 }
 
+impl<T> CycleError<T>
+where T: fmt::Debug + fmt::Display + Any {
+    /// The nodes that form the cycle.
+    pub fn cycle(&self) -> &VecDeque<T> {
+        &self.cycle
+    }
+}
+
 impl<T> fmt::Display for CycleError<T>
 where T: fmt::Debug + fmt::Display + Any {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -141,6 +233,83 @@ where T: fmt::Debug + fmt::Display + Any {
     }
 }
 
+/// Finds the strongly connected components of a graph
+/// with Tarjan's algorithm.
+struct Components<'a, T: 'a>
+where T: Ord + Clone + Hash {
+    graph: &'a Graph<T>,
+
+    /// The order in which each node was visited.
+    index: BTreeMap<T, usize>,
+
+    /// The lowest index reachable from each node.
+    lowlink: BTreeMap<T, usize>,
+
+    /// The nodes that haven't been assigned a component yet.
+    stack: Vec<T>,
+    on_stack: BTreeSet<T>,
+
+    /// The components found so far.
+    found: Vec<BTreeSet<T>>,
+}
+
+impl<'a, T: 'a> Components<'a, T>
+where T: Ord + Clone + Hash {
+    fn new(graph: &'a Graph<T>) -> Components<'a, T> {
+        Components {
+            graph,
+            index: BTreeMap::new(),
+            lowlink: BTreeMap::new(),
+            stack: Vec::new(),
+            on_stack: BTreeSet::new(),
+            found: Vec::new(),
+        }
+    }
+
+    fn connect(&mut self, node: T) {
+        let index = self.index.len();
+
+        self.index.insert(node.clone(), index);
+        self.lowlink.insert(node.clone(), index);
+        self.stack.push(node.clone());
+        self.on_stack.insert(node.clone());
+
+        let graph = self.graph;
+
+        if let Some(neighbors) = graph.dependents_of(&node) {
+            for neighbor in neighbors {
+                if !self.index.contains_key(neighbor) {
+                    self.connect(neighbor.clone());
+
+                    let low = cmp::min(self.lowlink[&node], self.lowlink[neighbor]);
+                    self.lowlink.insert(node.clone(), low);
+                } else if self.on_stack.contains(neighbor) {
+                    let low = cmp::min(self.lowlink[&node], self.index[neighbor]);
+                    self.lowlink.insert(node.clone(), low);
+                }
+            }
+        }
+
+        // the node is the root of a component
+        if self.lowlink[&node] == self.index[&node] {
+            let mut component = BTreeSet::new();
+
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(&member);
+
+                let is_root = member == node;
+                component.insert(member);
+
+                if is_root {
+                    break;
+                }
+            }
+
+            self.found.push(component);
+        }
+    }
+}
+
 /// Encapsulates a topological sorting algorithm.
 ///
 /// Performs a topological sorting of the provided graph
@@ -164,6 +333,7 @@ where T: Ord + Clone + Hash {
 impl<'a, T: 'a> Topological<'a, T>
 where T: Ord + Clone + Hash {
     /// Construct the initial algorithm state.
+    #[allow(clippy::redundant_field_names)]
     fn new(graph: &'a Graph<T>) -> Topological<'a, T> {
         Topological {
             graph: graph,
@@ -177,6 +347,7 @@ where T: Ord + Clone + Hash {
     ///
     /// This uses a recursive depth-first search, as it facilitates
     /// keeping track of a cycle, if any is present.
+    #[allow(clippy::needless_borrow)]
     fn dfs(&mut self, node: T, out: &mut VecDeque<T>) -> Result<(), CycleError<T>>
     where T: fmt::Debug + fmt::Display + Any {
         self.on_stack.insert(node.clone());
@@ -217,7 +388,6 @@ where T: Ord + Clone + Hash {
     }
 
     /// ordering from select nodes
-    #[allow(dead_code)]
     pub fn from(mut self, nodes: Vec<T>) -> Result<Order<T>, CycleError<T>>
     where T: fmt::Display + fmt::Debug + Any {
         let mut order = VecDeque::new();
@@ -233,6 +403,7 @@ where T: Ord + Clone + Hash {
 
     /// the typical resolution algorithm, returns a topological ordering
     /// of the nodes which honors the dependencies
+    #[allow(clippy::needless_borrow)]
     pub fn all(mut self) -> Result<Order<T>, CycleError<T>>
     where T: fmt::Display + fmt::Debug + Any {
         let mut order = VecDeque::new();
@@ -251,6 +422,7 @@ where T: Ord + Clone + Hash {
 mod test {
     use super::Graph;
 
+    #[allow(clippy::needless_return)]
     fn helper_graph() -> Graph<usize> {
         let mut graph = Graph::new();
 
@@ -292,6 +464,34 @@ mod test {
         assert!(cycle.is_err());
     }
 
+    #[test]
+    fn find_every_cycle() {
+        let mut graph = Graph::new();
+
+        graph.add_edge(1, 2);
+        graph.add_edge(2, 3);
+        graph.add_edge(3, 1);
+
+        graph.add_edge(3, 4);
+
+        graph.add_edge(5, 6);
+        graph.add_edge(6, 5);
+
+        graph.add_edge(7, 7);
+
+        let cycles =
+            graph.cycles().iter()
+            .map(|c| c.cycle().iter().cloned().collect::<Vec<usize>>())
+            .collect::<Vec<Vec<usize>>>();
+
+        assert_eq!(cycles.len(), 3);
+        assert!(cycles.contains(&vec![1, 2, 3, 1]));
+        assert!(cycles.contains(&vec![5, 6, 5]));
+        assert!(cycles.contains(&vec![7, 7]));
+
+        assert!(helper_graph().cycles().is_empty());
+    }
+
     #[test]
     fn resolve_all() {
         let graph = helper_graph();
@@ -309,4 +509,14 @@ mod test {
 
         assert!(resolve_single.is_ok());
     }
+
+    #[test]
+    fn dependencies_closure() {
+        let graph = helper_graph();
+
+        let closure = graph.dependencies_closure(vec![4]);
+
+        assert_eq!(closure.into_iter().collect::<Vec<_>>(),
+                   vec![0, 2, 3, 4, 5, 6, 7, 8]);
+    }
 }
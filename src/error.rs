@@ -0,0 +1,389 @@
+//! Error reporting.
+//!
+//! Errors that occur while a rule's handlers run are annotated
+//! with the name of the rule, the route of the item being
+//! processed, if any, and the position of the failing handler
+//! within the handler chain(s), so that callers can determine
+//! exactly what failed.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::panic::Location;
+use std::path::{PathBuf, StripPrefixError};
+use std::str::Utf8Error;
+use std::string::FromUtf8Error;
+
+use glob;
+use regex;
+
+use dependency::CycleError;
+use item::{Claim, Route};
+
+/// Where an error occurred.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    /// The name of the rule whose handlers failed.
+    pub rule: Option<String>,
+
+    /// The route of the item being processed.
+    pub item: Option<Route>,
+
+    /// The position of the failing handler in each enclosing
+    /// handler chain, outermost first.
+    ///
+    /// e.g. `[1, 0]` refers to the first handler within the
+    /// second handler of the rule's chain.
+    pub position: Vec<usize>,
+}
+
+/// The errors that can occur while building a site.
+#[derive(Debug)]
+pub enum Error {
+    /// A file system operation failed.
+    Io(io::Error),
+
+    /// A pattern could not be parsed.
+    Pattern(String),
+
+    /// An item could not be routed.
+    Routing(String),
+
+    /// Items were routed to the same output path.
+    Collision {
+        path: PathBuf,
+
+        /// The claim that held the path, followed by the
+        /// claim that was refused.
+        claims: Vec<Claim>,
+    },
+
+    /// The rules' dependencies form a cycle.
+    ///
+    /// Each rule is paired with where it was defined, if known.
+    /// The first rule is repeated at the end to close the cycle, and
+    /// every rule is a dependency of the rule that follows it.
+    Cycle(Vec<(String, Option<&'static Location<'static>>)>),
+
+    /// A rule depends on rules that aren't registered.
+    UnknownDependencies {
+        rule: String,
+        location: Option<&'static Location<'static>>,
+        dependencies: Vec<String>,
+    },
+
+    /// The configuration is invalid.
+    Configuration(String),
+
+    /// A handler failed.
+    Handler(Box<dyn StdError + Send + Sync>),
+
+    /// An error that occurred while running a rule's handlers.
+    Within(Context, Box<Error>),
+
+    /// Every failure of a build that kept going after the first one.
+    Aggregate {
+        /// The failures, in the order they occurred.
+        errors: Vec<Error>,
+
+        /// The rules that were skipped because a rule
+        /// they depend on failed.
+        skipped: Vec<String>,
+    },
+}
+
+impl Error {
+    /// Construct an error from an arbitrary handler error.
+    pub fn handler<E>(error: E) -> Error
+    where E: Into<Box<dyn StdError + Send + Sync>> {
+        Error::Handler(error.into())
+    }
+
+    /// Wrap the error in a context, unless it already has one.
+    fn within(self) -> Error {
+        match self {
+            Error::Within(..) => self,
+            error => Error::Within(Context::default(), Box::new(error)),
+        }
+    }
+
+    /// Update the error's context, creating it if necessary.
+    fn annotate<F>(self, update: F) -> Error
+    where F: FnOnce(&mut Context) {
+        let mut error = self.within();
+
+        if let Error::Within(ref mut context, _) = error {
+            update(context);
+        }
+
+        error
+    }
+
+    /// Annotate the error with the position of the failing
+    /// handler within a chain.
+    pub fn at_position(self, position: usize) -> Error {
+        self.annotate(|context| context.position.insert(0, position))
+    }
+
+    /// Annotate the error with the item being processed.
+    ///
+    /// The innermost item is kept if the error was already annotated.
+    pub fn for_item(self, route: &Route) -> Error {
+        self.annotate(|context| {
+            if context.item.is_none() {
+                context.item = Some(route.clone());
+            }
+        })
+    }
+
+    /// Annotate the error with the rule being processed.
+    pub fn in_rule<S>(self, name: S) -> Error
+    where S: Into<String> {
+        self.annotate(|context| context.rule = Some(name.into()))
+    }
+
+    /// Where the error occurred, if known.
+    pub fn context(&self) -> Option<&Context> {
+        match *self {
+            Error::Within(ref context, _) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The name of the rule whose handlers failed, if any.
+    pub fn rule(&self) -> Option<&str> {
+        self.context().and_then(|c| c.rule.as_ref()).map(|r| &r[..])
+    }
+
+    /// The route of the item that failed, if any.
+    pub fn item(&self) -> Option<&Route> {
+        self.context().and_then(|c| c.item.as_ref())
+    }
+
+    /// The position of the failing handler, outermost first.
+    pub fn position(&self) -> &[usize] {
+        self.context().map_or(&[], |c| &c.position[..])
+    }
+
+    /// The underlying error, stripped of its context.
+    pub fn kind(&self) -> &Error {
+        match *self {
+            Error::Within(_, ref error) => error.kind(),
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Pattern(ref e) => write!(f, "invalid pattern: {}", e),
+            Error::Routing(ref e) => write!(f, "routing error: {}", e),
+            Error::Collision { ref path, ref claims } => {
+                let claims =
+                    claims.iter()
+                    .map(|claim| claim.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+
+                write!(f, "output path {:?} is claimed by more than one item: {}",
+                       path, claims)
+            },
+            Error::Cycle(ref cycle) => {
+                writeln!(f, "dependency cycle detected:")?;
+
+                for (idx, &(ref rule, location)) in cycle.iter().enumerate() {
+                    write!(f, "  {}. {}", idx + 1, rule)?;
+
+                    if let Some(location) = location {
+                        write!(f, " (defined at {}:{})", location.file(), location.line())?;
+                    }
+
+                    writeln!(f)?;
+                }
+
+                Ok(())
+            },
+            Error::UnknownDependencies { ref rule, location, ref dependencies } => {
+                let dependencies =
+                    dependencies.iter()
+                    .map(|d| format!("`{}`", d))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+
+                write!(f, "rule `{}` ", rule)?;
+
+                if let Some(location) = location {
+                    write!(f, "(defined at {}:{}) ", location.file(), location.line())?;
+                }
+
+                write!(f, "depends on unregistered rule(s) {}", dependencies)
+            },
+            Error::Configuration(ref e) => write!(f, "configuration error: {}", e),
+            Error::Handler(ref e) => e.fmt(f),
+            Error::Within(ref context, ref error) => {
+                if let Some(ref rule) = context.rule {
+                    writeln!(f, "in rule `{}`", rule)?;
+                }
+
+                if let Some(ref item) = context.item {
+                    writeln!(f, "  for item {:?}", item)?;
+                }
+
+                if !context.position.is_empty() {
+                    let position =
+                        context.position.iter()
+                        .map(|p| p.to_string())
+                        .collect::<Vec<String>>()
+                        .join(".");
+
+                    writeln!(f, "  at handler {}", position)?;
+                }
+
+                write!(f, "\n{}", error)
+            },
+            Error::Aggregate { ref errors, ref skipped } => {
+                writeln!(f, "{} error(s) occurred:", errors.len())?;
+
+                for (idx, error) in errors.iter().enumerate() {
+                    let error = error.to_string();
+                    let mut lines = error.lines();
+
+                    writeln!(f, "\n{}. {}", idx + 1, lines.next().unwrap_or(""))?;
+
+                    for line in lines {
+                        writeln!(f, "   {}", line)?;
+                    }
+                }
+
+                if !skipped.is_empty() {
+                    writeln!(f, "\nskipped rule(s) that depend on failed rules:")?;
+
+                    for rule in skipped {
+                        writeln!(f, "  {}", rule)?;
+                    }
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
+impl StdError for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::Pattern(_) => "invalid pattern",
+            Error::Routing(_) => "routing error",
+            Error::Collision { .. } => "output path collision",
+            Error::Cycle(_) => "dependency cycle detected",
+            Error::UnknownDependencies { .. } => "unregistered dependencies",
+            Error::Configuration(_) => "configuration error",
+            Error::Handler(_) => "handler error",
+            Error::Within(..) => "error while running a rule",
+            Error::Aggregate { .. } => "multiple errors occurred",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Handler(ref e) => Some(&**e),
+            Error::Within(_, ref e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<String> for Error {
+    fn from(error: String) -> Error {
+        Error::handler(error)
+    }
+}
+
+impl<'a> From<&'a str> for Error {
+    fn from(error: &'a str) -> Error {
+        Error::handler(error)
+    }
+}
+
+impl From<Box<dyn StdError + Send + Sync>> for Error {
+    fn from(error: Box<dyn StdError + Send + Sync>) -> Error {
+        Error::Handler(error)
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(error: Utf8Error) -> Error {
+        Error::handler(error)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(error: FromUtf8Error) -> Error {
+        Error::handler(error)
+    }
+}
+
+impl From<StripPrefixError> for Error {
+    fn from(error: StripPrefixError) -> Error {
+        Error::Routing(error.to_string())
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(error: regex::Error) -> Error {
+        Error::Pattern(error.to_string())
+    }
+}
+
+impl From<glob::PatternError> for Error {
+    fn from(error: glob::PatternError) -> Error {
+        Error::Pattern(error.to_string())
+    }
+}
+
+impl From<CycleError<String>> for Error {
+    fn from(error: CycleError<String>) -> Error {
+        Error::Cycle(error.cycle().iter().map(|rule| (rule.clone(), None)).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use item::Route;
+    use super::Error;
+
+    #[test]
+    fn accumulate_context() {
+        let inner = Route::Read(PathBuf::from("posts/inner.md"));
+        let outer = Route::Read(PathBuf::from("posts/outer.md"));
+
+        let error =
+            Error::Routing(String::from("no match"))
+            .at_position(2)
+            .for_item(&inner)
+            .at_position(1)
+            .for_item(&outer)
+            .in_rule("posts");
+
+        assert_eq!(error.rule(), Some("posts"));
+        assert_eq!(error.position(), &[1, 2]);
+        assert_eq!(error.item().and_then(|r| r.reading()), inner.reading());
+
+        match *error.kind() {
+            Error::Routing(ref message) => assert_eq!(message, "no match"),
+            ref other => panic!("unexpected error kind: {:?}", other),
+        }
+    }
+}
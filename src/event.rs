@@ -0,0 +1,305 @@
+//! Build events.
+//!
+//! The progress of a build is published as a stream of `BuildEvent`s
+//! to any number of observers, so that reporting is decoupled from
+//! the build itself. A `Site` reports to the console by default, but
+//! its observers can be replaced, e.g. to emit JSON for a CI system or
+//! to report nothing at all.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json;
+
+use item::{Claim, Route};
+use support::{self, seconds};
+
+pub static STARTING: &str = "  Starting";
+pub static FINISHED: &str = "  Finished";
+pub static FAILED: &str = "    Failed";
+pub static SKIPPED: &str = "   Skipped";
+pub static WARNING: &str = "   Warning";
+
+/// Something that happened during a build.
+///
+/// Events are serialized as objects whose `event` field is the
+/// kebab-cased name of the variant, e.g. `rule-finished`. Durations
+/// are in fractional seconds.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum BuildEvent {
+    /// The build started reading from the input directory.
+    BuildStarted {
+        input: PathBuf,
+    },
+
+    /// A rule was queued to be built.
+    RuleQueued {
+        rule: String,
+    },
+
+    /// A rule's handlers started running.
+    RuleStarted {
+        rule: String,
+    },
+
+    /// A rule's handlers finished successfully.
+    RuleFinished {
+        rule: String,
+        items: usize,
+        #[serde(serialize_with = "support::serialize_seconds")]
+        duration: Duration,
+    },
+
+    /// A rule's handlers failed.
+    RuleFailed {
+        rule: String,
+        error: String,
+    },
+
+    /// A rule wasn't built because a rule it depends on failed.
+    RuleSkipped {
+        rule: String,
+    },
+
+    /// An item's handlers failed.
+    ItemFailed {
+        rule: String,
+        #[serde(flatten)]
+        item: Route,
+        error: String,
+    },
+
+    /// Items were routed to the same output path, and the
+    /// configuration calls for a warning rather than an error.
+    OutputCollision {
+        path: PathBuf,
+        claims: Vec<Claim>,
+    },
+
+    /// A file that is no longer produced was removed from the output.
+    OutputRemoved {
+        path: PathBuf,
+    },
+
+    /// The statistics of the build couldn't be recorded.
+    StatisticsFailed {
+        error: String,
+    },
+
+    /// The build completed.
+    BuildComplete {
+        rules: usize,
+        failures: usize,
+        #[serde(serialize_with = "support::serialize_seconds")]
+        duration: Duration,
+    },
+}
+
+/// Something that is notified of build events.
+pub trait Observer {
+    fn observe(&self, event: &BuildEvent);
+}
+
+impl<F> Observer for F
+where F: Fn(&BuildEvent) {
+    fn observe(&self, event: &BuildEvent) {
+        self(event)
+    }
+}
+
+/// Publishes events to a set of observers.
+#[derive(Clone)]
+pub struct Events {
+    observers: Vec<Arc<dyn Observer + Sync + Send>>,
+}
+
+impl Events {
+    /// A publisher without any observers.
+    pub fn new() -> Events {
+        Events {
+            observers: vec![],
+        }
+    }
+
+    /// Register an observer.
+    pub fn observe<O>(&mut self, observer: O)
+    where O: Observer + Sync + Send + 'static {
+        self.observers.push(Arc::new(observer));
+    }
+
+    /// Remove every observer.
+    pub fn clear(&mut self) {
+        self.observers.clear();
+    }
+
+    /// Notify every observer of an event.
+    pub fn publish(&self, event: BuildEvent) {
+        for observer in &self.observers {
+            observer.observe(&event);
+        }
+    }
+
+    /// Publish the failure of a rule.
+    ///
+    /// Every failed item within the error is published as well.
+    pub fn publish_failure(&self, rule: &str, error: &::Error) {
+        self.publish_items(rule, error);
+
+        self.publish(BuildEvent::RuleFailed {
+            rule: String::from(rule),
+            error: error.to_string(),
+        });
+    }
+
+    fn publish_items(&self, rule: &str, error: &::Error) {
+        if let ::Error::Aggregate { ref errors, .. } = *error.kind() {
+            for error in errors {
+                self.publish_items(rule, error);
+            }
+        } else if let Some(item) = error.item() {
+            self.publish(BuildEvent::ItemFailed {
+                rule: String::from(rule),
+                item: item.clone(),
+                error: error.kind().to_string(),
+            });
+        }
+    }
+}
+
+impl Default for Events {
+    fn default() -> Events {
+        let mut events = Events::new();
+        events.observe(Console);
+        events
+    }
+}
+
+/// Reports events to the console.
+pub struct Console;
+
+impl Observer for Console {
+    fn observe(&self, event: &BuildEvent) {
+        use ansi_term::Colour::{Green, Red, Yellow};
+        use ansi_term::Style;
+
+        match *event {
+            BuildEvent::BuildStarted { ref input } => {
+                println!("building from {:?}", input);
+            },
+            BuildEvent::RuleStarted { ref rule } => {
+                println!("{} {}", Green.bold().paint(STARTING), rule);
+            },
+            BuildEvent::RuleFinished { ref rule, items, ref duration } => {
+                println!("{} {} [{}] {:.3}s",
+                    Style::default().bold().paint(FINISHED),
+                    rule,
+                    items,
+                    seconds(duration));
+            },
+            BuildEvent::RuleFailed { ref rule, .. } => {
+                println!("{} {}", Red.bold().paint(FAILED), rule);
+            },
+            BuildEvent::RuleSkipped { ref rule } => {
+                println!("{} {}", Yellow.bold().paint(SKIPPED), rule);
+            },
+            BuildEvent::OutputCollision { ref path, ref claims } => {
+                let claims =
+                    claims.iter()
+                    .map(|claim| claim.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+
+                println!("{} output path {:?} is claimed by more than one item: {}",
+                    Yellow.bold().paint(WARNING), path, claims);
+            },
+            BuildEvent::OutputRemoved { ref path } => {
+                println!("removing stale output {:?}", path);
+            },
+            BuildEvent::StatisticsFailed { ref error } => {
+                println!("{} could not record the build statistics: {}",
+                    Yellow.bold().paint(WARNING), error);
+            },
+            BuildEvent::BuildComplete { rules: 0, failures: 0, .. } => {
+                println!("there is nothing to do");
+            },
+            BuildEvent::RuleQueued { .. }
+            | BuildEvent::ItemFailed { .. }
+            | BuildEvent::BuildComplete { .. } => (),
+        }
+    }
+}
+
+/// Reports events as JSON, one object per line.
+pub struct Json<W>
+where W: Write {
+    writer: Mutex<W>,
+}
+
+impl Json<io::Stdout> {
+    /// Report to standard output.
+    pub fn stdout() -> Json<io::Stdout> {
+        Json::new(io::stdout())
+    }
+}
+
+impl<W> Json<W>
+where W: Write {
+    pub fn new(writer: W) -> Json<W> {
+        Json {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W> Observer for Json<W>
+where W: Write {
+    fn observe(&self, event: &BuildEvent) {
+        let mut writer = self.writer.lock().unwrap();
+
+        // there's nowhere to report a failure to report
+        if let Ok(json) = to_json(event) {
+            let _ = writeln!(writer, "{}", json);
+            let _ = writer.flush();
+        }
+    }
+}
+
+/// Encode an event as a JSON object.
+///
+/// Fails if a path within the event isn't valid UTF-8.
+pub fn to_json(event: &BuildEvent) -> serde_json::Result<String> {
+    serde_json::to_string(event)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use item::Route;
+    use super::{BuildEvent, to_json};
+
+    #[test]
+    fn encode_json() {
+        let finished = BuildEvent::RuleFinished {
+            rule: String::from("posts"),
+            items: 3,
+            duration: Duration::from_millis(1500),
+        };
+
+        assert_eq!(to_json(&finished).unwrap(),
+                   r#"{"event":"rule-finished","rule":"posts","items":3,"duration":1.5}"#);
+
+        let failed = BuildEvent::ItemFailed {
+            rule: String::from("post \"index\""),
+            item: Route::Write(PathBuf::from("index.html")),
+            error: String::from("line\nbreak"),
+        };
+
+        assert_eq!(to_json(&failed).unwrap(),
+                   r#"{"event":"item-failed","rule":"post \"index\"","source":null,"target":"index.html","error":"line\nbreak"}"#);
+    }
+}
@@ -0,0 +1,148 @@
+//! Rule graph export.
+//!
+//! The rules and the dependencies between them can be exported as
+//! Graphviz DOT or as JSON, annotated with what each rule did the last
+//! time it was built, to help figure out why a rule waits as long as
+//! it does before it starts.
+
+use std::fmt::Write;
+
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use serde_json;
+
+use cache::Statistics;
+use support::seconds;
+
+/// A rule within the graph.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub name: String,
+
+    /// The names of the rules this rule depends on.
+    pub dependencies: Vec<String>,
+
+    /// What the rule did the last time it was built, if it ever was.
+    pub statistics: Option<Statistics>,
+}
+
+/// Serialized with the item count and duration of the statistics
+/// in place of them, which are `null` for rules that haven't been built.
+impl Serialize for Node {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        let mut node = serializer.serialize_struct("Node", 4)?;
+        node.serialize_field("name", &self.name)?;
+        node.serialize_field("dependencies", &self.dependencies)?;
+        node.serialize_field("items", &self.statistics.map(|s| s.items))?;
+        node.serialize_field("duration", &self.statistics.map(|s| seconds(&s.duration)))?;
+        node.end()
+    }
+}
+
+/// The rule dependency graph.
+#[derive(Serialize, Clone, Debug)]
+pub struct RuleGraph {
+    rules: Vec<Node>,
+}
+
+impl RuleGraph {
+    pub fn new(rules: Vec<Node>) -> RuleGraph {
+        RuleGraph {
+            rules,
+        }
+    }
+
+    pub fn rules(&self) -> &[Node] {
+        &self.rules
+    }
+
+    /// Encode the graph in the Graphviz DOT language.
+    ///
+    /// Edges point from a dependency to the rules that depend on it.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rules {\n");
+
+        for rule in &self.rules {
+            let label = match rule.statistics {
+                Some(ref statistics) => {
+                    format!("{}\\n{} item(s), {:.3}s",
+                            escape(&rule.name),
+                            statistics.items,
+                            seconds(&statistics.duration))
+                },
+                None => escape(&rule.name),
+            };
+
+            writeln!(dot, "    \"{}\" [label=\"{}\"];", escape(&rule.name), label).unwrap();
+        }
+
+        for rule in &self.rules {
+            for dependency in &rule.dependencies {
+                writeln!(dot, "    \"{}\" -> \"{}\";",
+                         escape(dependency),
+                         escape(&rule.name)).unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Encode the graph as a JSON object.
+    ///
+    /// The item count and duration are `null` for rules
+    /// that haven't been built.
+    pub fn to_json(&self) -> String {
+        // every key is a string, so this can't fail
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+/// Escape a string for use within a quoted DOT identifier.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use cache::Statistics;
+    use super::{Node, RuleGraph};
+
+    fn graph() -> RuleGraph {
+        RuleGraph::new(vec![
+            Node {
+                name: String::from("templates"),
+                dependencies: vec![],
+                statistics: Some(Statistics {
+                    items: 3,
+                    duration: Duration::from_millis(250),
+                }),
+            },
+            Node {
+                name: String::from("post \"index\""),
+                dependencies: vec![String::from("templates")],
+                statistics: None,
+            },
+        ])
+    }
+
+    #[test]
+    fn encode_dot() {
+        assert_eq!(graph().to_dot(), r#"digraph rules {
+    "templates" [label="templates\n3 item(s), 0.250s"];
+    "post \"index\"" [label="post \"index\""];
+    "templates" -> "post \"index\"";
+}
+"#);
+    }
+
+    #[test]
+    fn encode_json() {
+        assert_eq!(graph().to_json(),
+                   concat!(r#"{"rules":[{"name":"templates","dependencies":[],"items":3,"duration":0.25},"#,
+                           r#"{"name":"post \"index\"","dependencies":["templates"],"items":null,"duration":null}]}"#));
+    }
+}
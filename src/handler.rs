@@ -13,12 +13,14 @@ where H: Handle<T> {
     }
 }
 
+#[allow(bare_trait_objects)]
 impl<T> Handle<T> for Box<Handle<T>> {
     fn handle(&self, target: &mut T) -> ::Result<()> {
         (**self).handle(target)
     }
 }
 
+#[allow(bare_trait_objects)]
 impl<T> Handle<T> for Box<Handle<T> + Sync + Send> {
     fn handle(&self, target: &mut T) -> ::Result<()> {
         (**self).handle(target)
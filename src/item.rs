@@ -1,12 +1,22 @@
 //! Compilation unit for the `Generator`.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug};
+use std::fs;
+use std::io;
+use std::process;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::path::{PathBuf, Path};
+use std::mem;
+use std::str;
 
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeStruct;
 use typemap::{CloneAny, TypeMap};
 
 use bind;
+use support;
 
 /// The route of an `Item`.
 #[derive(Clone)]
@@ -21,6 +31,19 @@ pub enum Route {
     ReadWrite(PathBuf, PathBuf),
 }
 
+/// Serialized as the file being read from and the file being
+/// written to, as `source` and `target`, either of which is `null`
+/// if there is none.
+impl Serialize for Route {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer {
+        let mut route = serializer.serialize_struct("Route", 2)?;
+        route.serialize_field("source", &self.reading())?;
+        route.serialize_field("target", &self.writing())?;
+        route.end()
+    }
+}
+
 // TODO
 // rename writing/reading methods
 // to avoid confusion with Item constructors
@@ -58,6 +81,21 @@ impl Route {
         }
     }
 
+    /// Route to the given target.
+    ///
+    /// A `Read` becomes a `ReadWrite`, and the write path
+    /// of a `Write` or `ReadWrite` is replaced.
+    pub fn route_to(&mut self, target: PathBuf) {
+        use std::mem;
+
+        let current = mem::replace(self, Route::Read(PathBuf::new()));
+
+        *self = match current {
+            Route::Read(from) | Route::ReadWrite(from, _) => Route::ReadWrite(from, target),
+            Route::Write(_) => Route::Write(target),
+        };
+    }
+
     /// Apply a router to this route.
     ///
     /// The semantics are as follows:
@@ -107,14 +145,257 @@ impl Debug for Route {
     }
 }
 
+/// An item's claim on an output path.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Claim {
+    /// The name of the rule whose bind the item belongs to.
+    pub rule: String,
+
+    /// The file the item reads from, if any.
+    pub source: Option<PathBuf>,
+}
+
+/// The claims on the paths of the output directory.
+///
+/// Every bind of a build shares the same claims. An item claims its
+/// target right before it's written, so that when items are routed to
+/// the same path, the rule that comes earlier in the order in which
+/// the rules are resolved keeps it, no matter which finishes first.
+#[derive(Default, Debug)]
+pub struct Claims {
+    /// The claim that each written path is held by, relative
+    /// to the output directory
+    written: BTreeMap<PathBuf, Claim>,
+
+    /// The position of each rule in the resolved order
+    order: BTreeMap<String, usize>,
+
+    /// The collisions that haven't been reported yet, each with the
+    /// claim that held, followed by the claim that was refused
+    collisions: Vec<(PathBuf, Vec<Claim>)>,
+}
+
+impl Claims {
+    /// Set the order in which the rules are resolved.
+    pub fn set_order<I>(&mut self, order: I)
+    where I: IntoIterator<Item = String> {
+        self.order = order.into_iter().enumerate().map(|(idx, rule)| (rule, idx)).collect();
+    }
+
+    /// Claim a path, relative to the output directory.
+    ///
+    /// Returns whether the claim holds, which is the case unless the
+    /// path was already claimed by another item whose rule comes
+    /// earlier in the resolved order, or by another item of the same
+    /// rule. Either way, a collision is recorded.
+    pub fn claim(&mut self, path: PathBuf, claim: Claim) -> bool {
+        let previous = match self.written.get(&path) {
+            Some(previous) if *previous == claim => return true,
+            Some(previous) => previous.clone(),
+            None => {
+                self.written.insert(path, claim);
+                return true;
+            },
+        };
+
+        let position = |claim: &Claim| self.order.get(&claim.rule).cloned().unwrap_or(usize::MAX);
+        let holds = position(&claim) < position(&previous);
+
+        if holds {
+            self.collisions.push((path.clone(), vec![claim.clone(), previous]));
+            self.written.insert(path, claim);
+        } else {
+            self.collisions.push((path, vec![previous, claim]));
+        }
+
+        holds
+    }
+
+    /// Forget the paths claimed by the given rules, e.g. because
+    /// they're about to be built again.
+    pub fn release(&mut self, rules: &BTreeSet<String>) {
+        self.written.retain(|_, claim| !rules.contains(&claim.rule));
+    }
+
+    /// The written paths and the rule that wrote each.
+    pub fn written(&self) -> BTreeMap<PathBuf, String> {
+        self.written.iter()
+            .map(|(path, claim)| (path.clone(), claim.rule.clone()))
+            .collect()
+    }
+
+    /// Take the collisions that haven't been reported yet.
+    pub fn take_collisions(&mut self) -> Vec<(PathBuf, Vec<Claim>)> {
+        mem::take(&mut self.collisions)
+    }
+}
+
+impl fmt::Display for Claim {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rule `{}`", self.rule)?;
+
+        if let Some(ref source) = self.source {
+            write!(f, " from {:?}", source)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The contents of an `Item`.
+///
+/// A body is either text or raw bytes. Conversions between the two
+/// are lossless: bytes are only ever turned into text if they're
+/// valid UTF-8.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Body {
+    /// UTF-8 text, e.g. a markdown document.
+    Text(String),
+
+    /// Arbitrary data, e.g. an image or a font.
+    Bytes(Vec<u8>),
+}
+
+impl Body {
+    /// Whether the body is text.
+    pub fn is_text(&self) -> bool {
+        match *self {
+            Body::Text(_) => true,
+            Body::Bytes(_) => false,
+        }
+    }
+
+    /// Whether the body is raw bytes.
+    pub fn is_bytes(&self) -> bool {
+        !self.is_text()
+    }
+
+    /// Whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The length of the body in bytes.
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    /// The body as bytes, regardless of its representation.
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            Body::Text(ref text) => text.as_bytes(),
+            Body::Bytes(ref bytes) => bytes,
+        }
+    }
+
+    /// The body as text, if it's text or bytes that are valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Body::Text(ref text) => Some(text),
+            Body::Bytes(ref bytes) => str::from_utf8(bytes).ok(),
+        }
+    }
+
+    /// Mutable access to the body as text.
+    ///
+    /// Bytes are converted to text in-place, failing if
+    /// they aren't valid UTF-8.
+    pub fn text_mut(&mut self) -> ::Result<&mut String> {
+        if let Body::Bytes(ref mut bytes) = *self {
+            let taken = mem::take(bytes);
+
+            match String::from_utf8(taken) {
+                Ok(text) => *self = Body::Text(text),
+                Err(e) => {
+                    let error = e.utf8_error();
+                    *bytes = e.into_bytes();
+                    return Err(::Error::handler(error));
+                },
+            }
+        }
+
+        match *self {
+            Body::Text(ref mut text) => Ok(text),
+            Body::Bytes(_) => unreachable!(),
+        }
+    }
+
+    /// Mutable access to the body as bytes.
+    ///
+    /// Text is converted to bytes in-place.
+    pub fn bytes_mut(&mut self) -> &mut Vec<u8> {
+        if let Body::Text(ref mut text) = *self {
+            let bytes = mem::take(text).into_bytes();
+            *self = Body::Bytes(bytes);
+        }
+
+        match *self {
+            Body::Bytes(ref mut bytes) => bytes,
+            Body::Text(_) => unreachable!(),
+        }
+    }
+
+    /// Convert the body into text.
+    ///
+    /// If the bytes aren't valid UTF-8 they're handed back unchanged.
+    pub fn into_string(self) -> Result<String, Vec<u8>> {
+        match self {
+            Body::Text(text) => Ok(text),
+            Body::Bytes(bytes) => String::from_utf8(bytes).map_err(|e| e.into_bytes()),
+        }
+    }
+
+    /// Convert the body into bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Body::Text(text) => text.into_bytes(),
+            Body::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Body {
+        Body::Text(String::new())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Text(text)
+    }
+}
+
+impl<'a> From<&'a str> for Body {
+    fn from(text: &'a str) -> Body {
+        Body::Text(String::from(text))
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Body::Text(ref text) => write!(f, "Text({} bytes)", text.len()),
+            Body::Bytes(ref bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+        }
+    }
+}
+
 /// Represents a file to be processed.
 
 #[derive(Clone)]
 pub struct Item {
     /// The data that was read or that is to be written
-    pub body: String,
+    pub body: Body,
 
     /// Arbitrary additional data
+    #[allow(bare_trait_objects)]
     pub extensions: TypeMap<CloneAny + Sync + Send>,
 
     bind: Option<Arc<bind::Data>>,
@@ -126,12 +407,13 @@ pub struct Item {
 // have Item::read/Item.read that gets delegated
 // to by the read/write handlers?
 impl Item {
+    #[allow(clippy::redundant_field_names)]
     pub fn new(route: Route) -> Item {
         Item {
             bind: None,
             route: route,
 
-            body: String::new(),
+            body: Body::default(),
             extensions: TypeMap::custom(),
         }
     }
@@ -160,6 +442,12 @@ impl Item {
         &self.route
     }
 
+    /// Route the item to the given target.
+    pub fn route_to<P>(&mut self, target: P)
+    where P: Into<PathBuf> {
+        self.route.route_to(target.into())
+    }
+
     /// Route the item with the given router.
     pub fn route_with<R>(&mut self, router: R)
     where R: Fn(&Path) -> PathBuf {
@@ -167,21 +455,133 @@ impl Item {
     }
 
     /// The path to the underlying file being read.
-    pub fn source(&self) -> Option<PathBuf> {
-        self.route.reading().map(|from| {
-            self.bind.as_ref().map_or_else(
-                || from.to_path_buf(),
-                |b| b.configuration.input.join(from))
-        })
+    ///
+    /// The route is normalized within the input directory. Fails if
+    /// the route is absolute or escapes the input directory.
+    pub fn source(&self) -> ::Result<Option<PathBuf>> {
+        match (self.route.reading(), self.bind.as_ref()) {
+            (Some(from), Some(bind)) => {
+                self.confine(from, "input").map(|from| Some(bind.configuration.input.join(from)))
+            },
+            (from, None) => Ok(from.map(Path::to_path_buf)),
+            (None, _) => Ok(None),
+        }
     }
 
     /// The path to the underlying file being written to.
-    pub fn target(&self) -> Option<PathBuf> {
-        self.route.writing().map(|to| {
-            self.bind.as_ref().map_or_else(
-                || to.to_path_buf(),
-                |b| b.configuration.output.join(to))
-        })
+    ///
+    /// The route is normalized within the output directory. Fails if
+    /// the route is absolute or escapes the output directory.
+    pub fn target(&self) -> ::Result<Option<PathBuf>> {
+        match (self.route.writing(), self.bind.as_ref()) {
+            (Some(to), Some(bind)) => {
+                self.confine(to, "output").map(|to| Some(bind.configuration.output.join(to)))
+            },
+            (to, None) => Ok(to.map(Path::to_path_buf)),
+            (None, _) => Ok(None),
+        }
+    }
+
+    /// Normalize a path of the item's route, which must name
+    /// a file within the given directory.
+    fn confine(&self, path: &Path, directory: &str) -> ::Result<PathBuf> {
+        match support::normalize(path) {
+            Some(ref normalized) if normalized.as_os_str().is_empty() => {
+                Err(::Error::Routing(
+                    format!("{:?} doesn't name a file within the {} directory",
+                            path, directory))
+                    .for_item(&self.route))
+            },
+            Some(normalized) => Ok(normalized),
+            None => {
+                Err(::Error::Routing(
+                    format!("{:?} is outside of the {} directory", path, directory))
+                    .for_item(&self.route))
+            },
+        }
+    }
+
+    /// Write the item's target, if it has one, with a function that
+    /// writes the file at the path it's given.
+    ///
+    /// The file is written beside the target and then moved into place,
+    /// but only if the item's claim on the target holds. Otherwise the
+    /// target is left as it is, and the collision is reported once the
+    /// rule finishes. See `Claims::claim`.
+    pub fn write_target<F>(&self, write: F) -> ::Result<()>
+    where F: FnOnce(&Path) -> io::Result<()> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let to = match self.target()? {
+            Some(to) => to,
+            None => return Ok(()),
+        };
+
+        if let Some(parent) = to.parent() {
+            support::mkdir_p(parent)?;
+        }
+
+        let temporary = to.with_file_name(format!(
+            ".{}.{}-{}.tmp",
+            to.file_name().unwrap().to_string_lossy(),
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)));
+
+        if let Err(e) = write(&temporary) {
+            let _ = fs::remove_file(&temporary);
+            return Err(::Error::Io(e));
+        }
+
+        self.claim(|holds| {
+            if holds {
+                fs::rename(&temporary, &to)
+            } else {
+                fs::remove_file(&temporary)
+            }
+        })?;
+
+        Ok(())
+    }
+
+    /// Claim the item's target after it was written by other means
+    /// than `write_target`.
+    ///
+    /// This keeps the target from being pruned as stale output after
+    /// the build, and reports a collision if another item claimed it.
+    /// `item::write` and `item::copy` claim the target on their own,
+    /// but handlers that write it by other means should do this too.
+    pub fn mark_written(&self) {
+        let _ = self.claim(|_| Ok(()));
+    }
+
+    /// Claim the item's target and call `commit` with whether the claim
+    /// holds, while no other item can claim it.
+    ///
+    /// The claim always holds if the item isn't part of a build.
+    fn claim<F>(&self, commit: F) -> io::Result<()>
+    where F: FnOnce(bool) -> io::Result<()> {
+        use util::handle::bind::OutputPaths;
+
+        let to = self.route.writing().and_then(support::normalize);
+
+        let (bind, to) = match (self.bind.as_ref(), to) {
+            (Some(bind), Some(to)) => (bind, to),
+            _ => return commit(true),
+        };
+
+        let claims = match bind.extensions.read().unwrap().get::<OutputPaths>() {
+            Some(claims) => claims.clone(),
+            None => return commit(true),
+        };
+
+        let mut claims = claims.lock().unwrap();
+
+        let holds = claims.claim(to, Claim {
+            rule: bind.name.clone(),
+            source: self.route.reading().map(Path::to_path_buf),
+        });
+
+        commit(holds)
     }
 
     /// Access the bind's data
@@ -206,3 +606,70 @@ impl Debug for Item {
     }
 }
 
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use bind;
+    use configuration::Configuration;
+    use super::{Body, Item};
+
+    #[test]
+    fn convert_valid_bytes_to_text() {
+        let mut body = Body::from(b"hello".to_vec());
+
+        assert!(body.is_bytes());
+        assert_eq!(body.as_str(), Some("hello"));
+
+        body.text_mut().unwrap().push_str(" world");
+
+        assert!(body.is_text());
+        assert_eq!(body.as_bytes(), b"hello world");
+    }
+
+    #[test]
+    fn keep_invalid_bytes_intact() {
+        let bytes = vec![0xff, 0xfe, 0x00, 0x89];
+        let mut body = Body::from(bytes.clone());
+
+        assert!(body.as_str().is_none());
+        assert!(body.text_mut().is_err());
+        assert_eq!(body, Body::Bytes(bytes.clone()));
+        assert_eq!(body.into_string(), Err(bytes));
+    }
+
+    #[test]
+    fn convert_text_to_bytes() {
+        let mut body = Body::from("text");
+
+        body.bytes_mut().push(b'!');
+
+        assert!(body.is_bytes());
+        assert_eq!(body.into_string(), Ok(String::from("text!")));
+    }
+
+    #[test]
+    fn confine_routes() {
+        let configuration = Configuration::new().input("in").output("out");
+        let data = Arc::new(bind::Data::new(String::from("pages"), Arc::new(configuration)));
+
+        let attached = |mut item: Item| {
+            item.attach_to(data.clone());
+            item
+        };
+
+        let item = attached(Item::read_write("./posts/../about.md", "about/./index.html"));
+
+        assert_eq!(item.source().unwrap(), Some(PathBuf::from("in/about.md")));
+        assert_eq!(item.target().unwrap(), Some(PathBuf::from("out/about/index.html")));
+
+        for route in &["../../etc/passwd", "/etc/passwd", "posts/.."] {
+            let error = attached(Item::writing(*route)).target().unwrap_err();
+
+            assert_eq!(error.item().and_then(|r| r.writing()), Some(Path::new(route)));
+            assert!(attached(Item::reading(*route)).source().is_err());
+        }
+    }
+}
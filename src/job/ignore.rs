@@ -0,0 +1,267 @@
+//! Ignore files.
+//!
+//! A `.diecastignore` file within the input directory lists the paths
+//! beneath its own directory that are left out of the build, following
+//! the semantics of `.gitignore` files:
+//!
+//! * blank lines and lines starting with `#` are skipped
+//! * a pattern ending with `/` only matches directories
+//! * a pattern containing a `/` elsewhere is relative to the directory
+//!   of the ignore file, otherwise it matches a name at any depth
+//! * a pattern starting with `!` includes a path that an earlier
+//!   pattern ignored, unless one of its parent directories is ignored
+//!
+//! The last pattern that matches a path decides whether it's ignored,
+//! and the ignore files of deeper directories take precedence. If
+//! `Configuration::use_gitignore` is set, `.gitignore` files are read
+//! as well, with a `.diecastignore` taking precedence over the
+//! `.gitignore` in the same directory.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use glob::{self, MatchOptions};
+
+pub static FILE_NAME: &str = ".diecastignore";
+pub static GITIGNORE: &str = ".gitignore";
+
+struct Rule {
+    pattern: glob::Pattern,
+
+    /// Whether the pattern includes what it matches
+    is_negated: bool,
+
+    /// Whether the pattern only matches directories
+    is_directory_only: bool,
+
+    /// Whether the pattern is matched against the path relative to the
+    /// ignore file's directory rather than against the file name
+    is_anchored: bool,
+}
+
+/// The rules of the ignore files within a directory.
+pub struct Rules {
+    /// The directory, relative to the input directory
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Parse the contents of an ignore file.
+    ///
+    /// Lines that aren't valid patterns are skipped, as git does.
+    pub fn parse<P>(contents: &str, base: P) -> Rules
+    where P: Into<PathBuf> {
+        let rules =
+            contents.lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+
+                let (is_negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+
+                // a leading backslash escapes a literal `#` or `!`
+                let line = line.strip_prefix('\\').unwrap_or(line);
+
+                let (is_directory_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+
+                let is_anchored = line.contains('/');
+                let line = line.strip_prefix('/').unwrap_or(line);
+
+                glob::Pattern::new(line).ok().map(|pattern| Rule {
+                    pattern,
+                    is_negated,
+                    is_directory_only,
+                    is_anchored,
+                })
+            })
+            .collect();
+
+        Rules {
+            base: base.into(),
+            rules,
+        }
+    }
+
+    /// Read the ignore files within a directory.
+    ///
+    /// `base` is the directory relative to the input directory.
+    pub fn load(directory: &Path, base: &Path, use_gitignore: bool) -> Rules {
+        let mut names = vec![];
+
+        if use_gitignore {
+            names.push(GITIGNORE);
+        }
+
+        names.push(FILE_NAME);
+
+        let mut rules = Rules {
+            base: base.to_path_buf(),
+            rules: vec![],
+        };
+
+        for name in names {
+            let mut contents = String::new();
+
+            // a missing or unreadable ignore file ignores nothing
+            let read =
+                File::open(directory.join(name))
+                .and_then(|mut file| file.read_to_string(&mut contents));
+
+            if read.is_ok() {
+                rules.rules.extend(Rules::parse(&contents, base).rules);
+            }
+        }
+
+        rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the rules ignore or include the path, which is relative
+    /// to the input directory, or `None` if no rule matches it.
+    fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            .. MatchOptions::new()
+        };
+
+        let relative = path.strip_prefix(&self.base).ok()?;
+
+        self.rules.iter()
+            .rev()
+            .find(|rule| {
+                if rule.is_directory_only && !is_dir {
+                    return false;
+                }
+
+                if rule.is_anchored {
+                    rule.pattern.matches_path_with(relative, options)
+                } else {
+                    relative.file_name().is_some_and(|name| {
+                        rule.pattern.matches_with(&name.to_string_lossy(), options)
+                    })
+                }
+            })
+            .map(|rule| !rule.is_negated)
+    }
+}
+
+/// Whether a path relative to the input directory is ignored by the
+/// rules of the directories that contain it, outermost first.
+pub fn is_ignored<'a, I>(scopes: I, path: &Path, is_dir: bool) -> bool
+where I: DoubleEndedIterator<Item = &'a Rules> {
+    scopes.rev()
+        .filter_map(|rules| rules.decide(path, is_dir))
+        .next()
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use configuration::Configuration;
+    use job::enumerate;
+    use support::TempDir;
+    use super::{Rules, is_ignored};
+
+    #[test]
+    fn follow_gitignore_semantics() {
+        let root = Rules::parse("\
+# scratch files
+*.swp
+scratch/
+/notes.md
+drafts/*.md
+!drafts/keep.md
+\\#literal
+", "");
+
+        let nested = Rules::parse("!*.swp\n", "posts");
+
+        let ignored = |path: &str, is_dir: bool| {
+            is_ignored(vec![&root, &nested].into_iter(), Path::new(path), is_dir)
+        };
+
+        assert!(ignored("index.md.swp", false));
+        assert!(ignored("pages/about.md.swp", false));
+        assert!(!ignored("posts/hello.md.swp", false));
+
+        assert!(ignored("scratch", true));
+        assert!(ignored("posts/scratch", true));
+        assert!(!ignored("scratch", false));
+
+        assert!(ignored("notes.md", false));
+        assert!(!ignored("pages/notes.md", false));
+
+        assert!(ignored("drafts/unfinished.md", false));
+        assert!(!ignored("drafts/keep.md", false));
+        assert!(!ignored("drafts/nested/unfinished.md", false));
+
+        assert!(ignored("#literal", false));
+    }
+
+    #[test]
+    fn skip_ignored_inputs() {
+        let root = TempDir::new("ignore").unwrap();
+        let input = root.path();
+
+        for dir in &["posts/scratch", "pages", "vendor"] {
+            fs::create_dir_all(input.join(dir)).unwrap();
+        }
+
+        let files = [
+            "posts/hello.md", "posts/hello.md.swp", "posts/scratch/idea.md",
+            "pages/about.md", "vendor/lib.js",
+        ];
+
+        for file in &files {
+            fs::File::create(input.join(file)).unwrap();
+        }
+
+        fs::write(input.join(".diecastignore"), "*.swp\n").unwrap();
+        fs::write(input.join("posts/.diecastignore"), "scratch/\n").unwrap();
+        fs::write(input.join(".gitignore"), "vendor/\n").unwrap();
+
+        let enumerated = |configuration: &Configuration| {
+            let mut paths =
+                enumerate(configuration).into_iter()
+                .map(|path| path.strip_prefix(input).unwrap().to_path_buf())
+                .collect::<Vec<PathBuf>>();
+
+            paths.sort();
+            paths
+        };
+
+        let configuration = Configuration::new().input(input);
+
+        assert_eq!(enumerated(&configuration), vec![
+            PathBuf::from(".gitignore"),
+            PathBuf::from("pages/about.md"),
+            PathBuf::from("posts/hello.md"),
+            PathBuf::from("vendor/lib.js"),
+        ]);
+
+        let configuration = configuration.use_gitignore(true);
+
+        assert_eq!(enumerated(&configuration), vec![
+            PathBuf::from(".gitignore"),
+            PathBuf::from("pages/about.md"),
+            PathBuf::from("posts/hello.md"),
+        ]);
+    }
+}
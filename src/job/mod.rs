@@ -1,21 +1,22 @@
 use std::sync::Arc;
 use std::fmt;
-
-use time::PreciseTime;
+use std::time::{Duration, Instant};
 
 use bind::{self, Bind};
+use event::{BuildEvent, Events};
 use handler::Handle;
 
 mod scheduler;
+mod ignore;
+pub mod pool;
 
-pub use self::scheduler::Scheduler;
-
-pub static STARTING: &'static str = "  Starting";
-pub static FINISHED: &'static str = "  Finished";
+pub use self::scheduler::{Scheduler, enumerate};
 
 pub struct Job {
+    #[allow(bare_trait_objects)]
     pub handler: Arc<Handle<Bind> + Sync + Send>,
     pub bind: bind::Data,
+    pub events: Events,
 }
 
 impl fmt::Debug for Job {
@@ -25,45 +26,49 @@ impl fmt::Debug for Job {
 }
 
 impl Job {
+    #[allow(clippy::redundant_field_names)]
     pub fn new(
         bind: bind::Data,
-        handler: Arc<Handle<Bind> + Sync + Send>)
+        handler: Arc<dyn Handle<Bind> + Sync + Send>,
+        events: Events)
     -> Job {
         Job {
             handler: handler,
             bind: bind,
+            events: events,
         }
     }
 
-    pub fn process(self) -> ::Result<Bind> {
-        use ansi_term::Colour::Green;
-        use ansi_term::Style;
-
+    /// Run the rule's handler on its bind.
+    ///
+    /// Returns the bind along with how long the handler took.
+    pub fn process(self) -> ::Result<(Bind, Duration)> {
         let mut bind = Bind::new(self.bind);
+        let name = bind.name.clone();
 
-        println!("{} {}",
-            Green.bold().paint(STARTING),
-            bind);
+        self.events.publish(BuildEvent::RuleStarted {
+            rule: name.clone(),
+        });
 
-        let start = PreciseTime::now();
+        let start = Instant::now();
         let res = self.handler.handle(&mut bind);
-        let end = PreciseTime::now();
-
-        let duration = start.to(end);
-
-        println!("{} {} [{}] {}",
-            Style::default().bold().paint(FINISHED),
-            bind,
-            bind.items().len(),
-            duration);
+        let duration = start.elapsed();
 
         match res {
-            Ok(_) => Ok(bind),
-            Err(e) =>
-                Err(From::from(
-                    format!("\nthe following job encountered an error:\n  {:?}\n\n{}\n",
-                            bind.name,
-                            e))),
+            Ok(()) => {
+                self.events.publish(BuildEvent::RuleFinished {
+                    rule: name,
+                    items: bind.items().len(),
+                    duration,
+                });
+
+                Ok((bind, duration))
+            },
+            Err(e) => {
+                let e = e.in_rule(name.clone());
+                self.events.publish_failure(&name, &e);
+                Err(e)
+            },
         }
     }
 }
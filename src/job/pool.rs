@@ -0,0 +1,216 @@
+//! Worker pool.
+//!
+//! A fixed set of worker threads shared by the rule jobs and by the
+//! per-item fan-out of handlers such as `bind::each`.
+//!
+//! A thread that fans out work onto the pool helps process that work
+//! while it waits for it to complete. This prevents a job that is
+//! waiting on its items from starving the pool, which would otherwise
+//! deadlock when there's a single worker, and it's what makes a pool
+//! of one worker process everything sequentially and in order.
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+struct State {
+    tasks: VecDeque<Task>,
+    shutdown: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+pub struct Pool {
+    shared: Arc<Shared>,
+    threads: usize,
+}
+
+impl Pool {
+    /// Spawn a pool with the given number of worker threads.
+    ///
+    /// There's always at least one worker.
+    pub fn new(threads: usize) -> Pool {
+        let threads = ::std::cmp::max(threads, 1);
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                tasks: VecDeque::new(),
+                shutdown: false,
+            }),
+            available: Condvar::new(),
+        });
+
+        for idx in 0 .. threads {
+            let shared = shared.clone();
+
+            thread::Builder::new()
+                .name(format!("diecast-worker-{}", idx))
+                .spawn(move || work(shared))
+                .expect("could not spawn a worker thread");
+        }
+
+        Pool {
+            shared,
+            threads,
+        }
+    }
+
+    /// The number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Run a task on the pool.
+    pub fn execute<F>(&self, task: F)
+    where F: FnOnce() + Send + 'static {
+        let mut state = self.shared.state.lock().unwrap();
+        state.tasks.push_back(Box::new(task));
+        self.shared.available.notify_one();
+    }
+
+    /// Run each of the tasks on the pool and wait for all of them.
+    ///
+    /// The outcomes are returned in the same order as the tasks. The
+    /// calling thread processes the tasks itself while it waits. A task
+    /// that panics yields `None`.
+    pub fn join<T>(&self, tasks: Vec<Box<dyn FnOnce() -> T + Send>>) -> Vec<Option<T>>
+    where T: Send + 'static {
+        let count = tasks.len();
+        let (sender, receiver) = channel();
+
+        let batch =
+            tasks.into_iter()
+            .enumerate()
+            .map(|(idx, task)| {
+                let sender = sender.clone();
+
+                Box::new(move || {
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(task)).ok();
+                    let _ = sender.send((idx, outcome));
+                }) as Task
+            })
+            .collect::<VecDeque<Task>>();
+
+        let batch = Arc::new(Mutex::new(batch));
+
+        // each ticket lets a worker take on one of the batch's tasks,
+        // if the calling thread hasn't already gotten to it
+        for _ in 0 .. count {
+            let batch = batch.clone();
+
+            self.execute(move || {
+                let task = batch.lock().unwrap().pop_front();
+
+                if let Some(task) = task {
+                    task();
+                }
+            });
+        }
+
+        loop {
+            let task = batch.lock().unwrap().pop_front();
+
+            match task {
+                Some(task) => task(),
+                None => break,
+            }
+        }
+
+        let mut outcomes = (0 .. count).map(|_| None).collect::<Vec<Option<T>>>();
+
+        for _ in 0 .. count {
+            let (idx, outcome) = receiver.recv().unwrap();
+            outcomes[idx] = outcome;
+        }
+
+        outcomes
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.shutdown = true;
+        self.shared.available.notify_all();
+    }
+}
+
+fn work(shared: Arc<Shared>) {
+    loop {
+        let task = {
+            let mut state = shared.state.lock().unwrap();
+
+            loop {
+                if let Some(task) = state.tasks.pop_front() {
+                    break task;
+                }
+
+                if state.shutdown {
+                    return;
+                }
+
+                state = shared.available.wait(state).unwrap();
+            }
+        };
+
+        // keep the worker alive if the task panics
+        let _ = panic::catch_unwind(AssertUnwindSafe(task));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::Pool;
+
+    #[test]
+    fn join_in_order() {
+        let pool = Pool::new(4);
+
+        let tasks =
+            (0 .. 32usize)
+            .map(|n| Box::new(move || n * 2) as Box<dyn FnOnce() -> usize + Send>)
+            .collect();
+
+        let outcomes = pool.join(tasks);
+
+        assert_eq!(outcomes, (0 .. 32).map(|n| Some(n * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn single_worker_is_sequential() {
+        let pool = Arc::new(Pool::new(1));
+        let order = Arc::new(Mutex::new(vec![]));
+        let (sender, receiver) = ::std::sync::mpsc::channel();
+
+        let inner = pool.clone();
+        let pushed = order.clone();
+
+        // fan out from within a task, which must not deadlock
+        pool.execute(move || {
+            let tasks =
+                (0 .. 8usize)
+                .map(|n| {
+                    let pushed = pushed.clone();
+                    Box::new(move || pushed.lock().unwrap().push(n))
+                        as Box<dyn FnOnce() + Send>
+                })
+                .collect();
+
+            inner.join(tasks);
+            sender.send(()).unwrap();
+        });
+
+        receiver.recv().unwrap();
+
+        assert_eq!(*order.lock().unwrap(), (0 .. 8).collect::<Vec<_>>());
+    }
+}
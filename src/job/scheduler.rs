@@ -1,20 +1,27 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::path::{PathBuf, Path};
-use std::collections::{BTreeMap, VecDeque, HashMap};
+use std::collections::{BTreeMap, BTreeSet, VecDeque, HashMap};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::panic::{self, AssertUnwindSafe};
 use std::mem;
+use std::time::Duration;
 
-use futures::prelude::*;
-use futures::{self, future, Future};
-
-use configuration::Configuration;
+use configuration::{Collisions, Configuration};
 use dependency::Graph;
+use event::{BuildEvent, Events};
+use item::Claims;
 use rule::Rule;
 use bind::{self, Bind};
 use super::Job;
+use super::ignore::{self, Rules};
+use super::pool::Pool;
 
 pub struct Scheduler {
     configuration: Arc<Configuration>,
 
+    /// Where build events are published
+    events: Events,
+
     rules: HashMap<String, Arc<Rule>>,
 
     graph: Graph<String>,
@@ -25,12 +32,28 @@ pub struct Scheduler {
     /// List of jobs that haven't been processed yet
     waiting: Vec<Job>,
 
-    /// List of jobs currently being processed
-    pending: Vec<Box<Future<Item = Bind, Error = ::Error>>>,
+    /// Number of jobs currently being processed
+    pending: usize,
+
+    /// The worker pool that jobs and their items are processed on,
+    /// sized by `Configuration::threads`
+    pool: Arc<Pool>,
+
+    /// Where processed jobs send their rule's name and their result
+    sender: Sender<(String, ::Result<(Bind, Duration)>)>,
+
+    /// Where the results of processed jobs are received
+    receiver: Receiver<(String, ::Result<(Bind, Duration)>)>,
 
     /// Finished dependencies
     finished: BTreeMap<String, Arc<Bind>>,
 
+    /// How long each finished job took
+    durations: BTreeMap<String, Duration>,
+
+    /// The claims on the paths written to the output directory
+    claims: Arc<Mutex<Claims>>,
+
     // TODO
     // feels weird to have this here, but it's in-line with making
     // matching Patterns first-class
@@ -39,15 +62,25 @@ pub struct Scheduler {
 }
 
 impl Scheduler {
-    pub fn new(configuration: Arc<Configuration>) -> Scheduler {
+    #[allow(clippy::redundant_field_names)]
+    pub fn new(configuration: Arc<Configuration>, events: Events) -> Scheduler {
+        let pool = Arc::new(Pool::new(configuration.threads));
+        let (sender, receiver) = channel();
+
         Scheduler {
             configuration: configuration,
+            events: events,
             rules: HashMap::new(),
             graph: Graph::new(),
             dependencies: BTreeMap::new(),
             waiting: Vec::new(),
-            pending: Vec::new(),
+            pending: 0,
+            pool: pool,
+            sender: sender,
+            receiver: receiver,
             finished: BTreeMap::new(),
+            durations: BTreeMap::new(),
+            claims: Arc::new(Mutex::new(Claims::default())),
             paths: Arc::new(Vec::new()),
         }
     }
@@ -60,50 +93,32 @@ impl Scheduler {
 
     /// Re-enumerate the paths in the input directory
     pub fn update_paths(&mut self) {
-        use walkdir::WalkDir;
-
-        let walked_paths =
-            WalkDir::new(&self.configuration.input)
-                .into_iter()
-                .filter_entry(|entry| {
-                    if let Some(ref ignore) = self.configuration.ignore {
-                        let file_name = &Path::new(entry.path().file_name().unwrap());
-
-                        if ignore.matches(file_name) {
-                            return false;
-                        }
-                    }
+        self.paths = Arc::new(enumerate(&self.configuration));
+    }
 
-                    true
-                })
-                .filter_map(|entry| entry.ok())
-                .filter_map(|entry| {
-                    entry.metadata()
-                        .map(|m| {
-                            if m.is_file() { Some(entry.path().to_path_buf()) }
-                            else { None }
-                        })
-                        .unwrap_or(None)
-                })
-                .collect();
+    /// The paths enumerated from the input directory
+    pub fn paths(&self) -> Arc<Vec<PathBuf>> {
+        self.paths.clone()
+    }
 
-        self.paths = Arc::new(walked_paths);
+    /// The binds of the jobs that finished
+    pub fn finished(&self) -> &BTreeMap<String, Arc<Bind>> {
+        &self.finished
     }
 
-    pub fn add(&mut self, rule: Arc<Rule>) {
-        // prepare bind-data with the name and configuration
-        let data = bind::Data::new(
-            String::from(rule.name()),
-            self.configuration.clone());
-        let name = data.name.clone();
+    /// How long each of the finished jobs took the last time it ran
+    pub fn durations(&self) -> &BTreeMap<String, Duration> {
+        &self.durations
+    }
 
-        // TODO
-        // instead of rule_count == 0,
-        // check if self.waiting.is_empty()?
+    /// The paths written to the output directory since the scheduler was
+    /// created, relative to it, and the rule whose claim on each held
+    pub fn written(&self) -> BTreeMap<PathBuf, String> {
+        self.claims.lock().unwrap().written()
+    }
 
-        // construct job from bind-data, rule kind, rule handler, and paths
-        // push it to waiting queue
-        self.waiting.push(Job::new(data, rule.handler()));
+    pub fn add(&mut self, rule: Arc<Rule>) {
+        let name = String::from(rule.name());
 
         self.graph.add_node(name.clone());
 
@@ -113,13 +128,78 @@ impl Scheduler {
         }
 
         self.rules.insert(name.clone(), rule);
+
+        self.enqueue(&name);
+    }
+
+    /// Queue a job for a rule that was already added.
+    fn enqueue(&mut self, name: &str) {
+        let rule = self.rules[name].clone();
+
+        // prepare bind-data with the name and configuration
+        let data = bind::Data::new(
+            String::from(rule.name()),
+            self.configuration.clone());
+
+        // construct job from bind-data, rule kind, rule handler, and paths
+        // push it to waiting queue
+        self.waiting.push(Job::new(data, rule.handler(), self.events.clone()));
+    }
+
+    /// The rules affected by changes to the given paths.
+    ///
+    /// These are the rules whose `bind::select` patterns match any of the
+    /// paths, the rules that didn't finish the last time they were built,
+    /// and every rule that transitively depends on any of those.
+    ///
+    /// The paths are expected to be relative to the input directory.
+    pub fn affected(&self, changed: &[PathBuf]) -> ::Result<BTreeSet<String>> {
+        use util::handle::bind::Selections;
+
+        let sources =
+            self.rules.keys()
+            .filter(|name| {
+                let bind = match self.finished.get(*name) {
+                    Some(bind) => bind,
+                    None => return true,
+                };
+
+                let extensions = bind.extensions.read().unwrap();
+
+                extensions.get::<Selections>().is_some_and(|patterns| {
+                    changed.iter().any(|path| {
+                        patterns.iter().any(|pattern| pattern.matches(path))
+                    })
+                })
+            })
+            .cloned()
+            .collect::<Vec<String>>();
+
+        Ok(self.graph.resolve(sources)?.into_iter().collect())
+    }
+
+    /// Queue the given rules to be built again.
+    ///
+    /// The binds of rules that aren't queued are retained from the
+    /// previous build, so that the queued rules can still access them
+    /// as dependencies.
+    ///
+    /// The paths written by the queued rules are forgotten, so that
+    /// the ones they no longer write can be told apart afterward.
+    pub fn requeue(&mut self, names: &BTreeSet<String>) {
+        self.claims.lock().unwrap().release(names);
+
+        for name in names {
+            // it'll only be considered finished if it succeeds again
+            self.finished.remove(name);
+
+            if self.rules.contains_key(name) {
+                self.enqueue(name);
+            }
+        }
     }
 
     // TODO: will need Borrow bound
-    // TODO
-    // should send the finished bind to a result channel
-    // this will enable decoupling of cli status messages
-    // from the core library
     fn satisfy(&mut self, current: Bind) {
         let bind_name = current.name.clone();
 
@@ -143,6 +223,7 @@ impl Scheduler {
         }
     }
 
+    #[allow(clippy::mem_replace_with_default)]
     fn ready(&mut self) -> Vec<Job> {
         let waiting = mem::replace(&mut self.waiting, Vec::new());
 
@@ -155,6 +236,7 @@ impl Scheduler {
         ready
     }
 
+    #[allow(unused_must_use, clippy::mem_replace_with_default, clippy::needless_return)]
     fn sort_jobs(&mut self, order: VecDeque<String>) {
         assert!(self.waiting.len() == order.len(),
                 "`waiting` and `order` are not the same length");
@@ -168,6 +250,8 @@ impl Scheduler {
             })
             .collect::<HashMap<String, Job>>();
 
+        let queued = job_map.keys().cloned().collect::<BTreeSet<String>>();
+
         // put the jobs into the order provided
         let ordered =
             order.into_iter()
@@ -177,9 +261,17 @@ impl Scheduler {
                 // set dep counts
                 let name = job.bind.name.clone();
 
-                let count = self.graph.dependency_count(&name);
+                // dependencies that aren't queued were
+                // satisfied by a previous build
+                let count =
+                    self.graph.dependencies_of(&name)
+                    .map_or(0, |deps| deps.intersection(&queued).count());
+
+                *self.dependencies.entry(name.clone()).or_insert(0) += count;
 
-                *self.dependencies.entry(name).or_insert(0) += count;
+                self.events.publish(BuildEvent::RuleQueued {
+                    rule: name,
+                });
 
                 return job;
             })
@@ -191,69 +283,191 @@ impl Scheduler {
     }
 
     pub fn build(&mut self) -> ::Result<()> {
-        use util::handle::bind::InputPaths;
+        use util::handle::bind::{InputPaths, OutputPaths, WorkerPool};
 
         if self.waiting.is_empty() {
-            println!("there is nothing to do");
             return Ok(());
         }
 
         for job in &mut self.waiting {
-            job.bind.extensions.write().unwrap()
-                .insert::<InputPaths>(self.paths.clone());
+            let mut extensions = job.bind.extensions.write().unwrap();
+
+            extensions.insert::<InputPaths>(self.paths.clone());
+            extensions.insert::<WorkerPool>(self.pool.clone());
+            extensions.insert::<OutputPaths>(self.claims.clone());
         }
 
         // NOTE
         //
-        // using futures_cpupool
+        // * Each ready job is executed on the worker pool, which sends
+        // the result back on the scheduler's channel.
         //
-        // * For each ready job, spawn it on the cpupool and add it to a vector
-        // of pending jobs.
-        //
-        // * In the main loop perform a select_all().wait() on the vector to
-        // wait for the first available job. select_all() returns a triple of:
-        //
-        //   1. resolved value
-        //   2. index of resolved future within pending list
-        //   3. original vector, with resolved future removed
-        //
-        // * When a future is resolve (i.e. job is ready), enqueue all ready
-        // other ready jobs
+        // * The main loop waits for the next result. When a job finishes,
+        // the jobs that were waiting on it may become ready, so those
+        // are executed as well.
 
-        let order = self.graph.resolve_all()?;
+        let order = {
+            let queued =
+                self.waiting.iter()
+                .map(|job| job.bind.name.clone())
+                .collect::<BTreeSet<String>>();
+
+            let order = self.graph.resolve_all()?;
+
+            self.claims.lock().unwrap().set_order(order.iter().cloned());
+
+            order.into_iter()
+                .filter(|name| queued.contains(name))
+                .collect::<VecDeque<String>>()
+        };
 
         self.sort_jobs(order);
         self.schedule_ready();
 
-        while !self.pending.is_empty() {
-            let pending = mem::replace(&mut self.pending, Vec::new());
+        let mut errors = Vec::new();
+        let mut skipped = Vec::new();
+
+        // the rules that failed, including those that were blamed for
+        // a collision after they finished or while they were running
+        let mut failed = BTreeSet::new();
+
+        while self.pending > 0 {
+            let (name, result) = self.receiver.recv().unwrap();
+            self.pending -= 1;
+
+            let (finished, mut failures) = match result {
+                Ok(finished) => (Some(finished), vec![]),
+                Err(e) => (None, vec![e]),
+            };
 
-            match futures::executor::block_on(future::select_all(pending)) {
-                Ok((bind, _index, mut new_pending)) => {
-                    mem::swap(&mut new_pending, &mut self.pending);
+            failures.extend(self.collisions());
 
+            if failures.is_empty() && !failed.contains(&name) {
+                if let Some((bind, duration)) = finished {
+                    self.durations.insert(name, duration);
                     self.satisfy(bind);
-                    self.schedule_ready();
                 }
-                Err((e, _index, _new_pending)) => {
-                    return Err(
-                        From::from(
-                            format!("a job panicked. stopping everything:\n{}", e)));
+
+                self.schedule_ready();
+                continue;
+            }
+
+            if !self.configuration.is_keep_going && !failures.is_empty() {
+                // stop everything
+                self.wait_pending();
+                self.reset();
+
+                return Err(match failures.len() {
+                    1 => failures.pop().unwrap(),
+                    _ => ::Error::Aggregate { errors: failures, skipped: vec![] },
+                });
+            }
+
+            for failure in &failures {
+                let rule = String::from(failure.rule().unwrap_or(&name));
+
+                if failed.insert(rule.clone()) {
+                    skipped.extend(self.skip_dependents(&rule));
                 }
             }
+
+            errors.extend(failures);
+            self.schedule_ready();
         }
 
         // TODO
         // no longer necessary post-partial update purge?
         self.reset();
 
-        Ok(())
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(::Error::Aggregate {
+                errors,
+                skipped,
+            })
+        }
+    }
+
+    /// Report the collisions since the last time this was called.
+    ///
+    /// The rule whose claim was refused is blamed. Each collision is
+    /// either an error for that rule or a warning, per
+    /// `Configuration::collisions`. Returns the errors.
+    fn collisions(&mut self) -> Vec<::Error> {
+        let collisions = self.claims.lock().unwrap().take_collisions();
+
+        collisions.into_iter()
+            .filter_map(|(path, claims)| {
+                let rule = claims.last().unwrap().rule.clone();
+
+                match self.configuration.collisions {
+                    Collisions::Error => {
+                        let error = ::Error::Collision {
+                            path,
+                            claims,
+                        }.in_rule(rule.clone());
+
+                        self.events.publish_failure(&rule, &error);
+
+                        Some(error)
+                    },
+                    Collisions::Warn => {
+                        self.events.publish(BuildEvent::OutputCollision {
+                            path,
+                            claims,
+                        });
+
+                        None
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Remove the jobs that transitively depend on a failed rule.
+    ///
+    /// Returns the names of the rules that were skipped.
+    fn skip_dependents(&mut self, failed: &str) -> Vec<String> {
+        let dependents =
+            self.graph.resolve(vec![String::from(failed)])
+            .map(|order| order.into_iter().filter(|name| name != failed).collect())
+            .unwrap_or_else(|_| BTreeSet::new());
+
+        let waiting = mem::take(&mut self.waiting);
+
+        let (skipped, waiting): (Vec<Job>, Vec<Job>) =
+            waiting.into_iter()
+            .partition(|job| dependents.contains(&job.bind.name));
+
+        self.waiting = waiting;
+
+        skipped.into_iter()
+            .map(|job| {
+                self.events.publish(BuildEvent::RuleSkipped {
+                    rule: job.bind.name.clone(),
+                });
+
+                job.bind.name
+            })
+            .collect()
     }
 
     // TODO: audit
+    // the graph, rules, and finished binds are kept around so that
+    // the scheduler can be re-entered to rebuild a subset of the rules
     fn reset(&mut self) {
-        self.graph = Graph::new();
         self.waiting.clear();
+        self.dependencies.clear();
+    }
+
+    /// Wait for the jobs that are still being processed,
+    /// discarding their results.
+    fn wait_pending(&mut self) {
+        while self.pending > 0 {
+            let _ = self.receiver.recv();
+            self.pending -= 1;
+        }
     }
 
     fn schedule_ready(&mut self) {
@@ -271,8 +485,83 @@ impl Scheduler {
                 }
             }
 
-            let spawned = futures::executor::block_on(futures::executor::spawn_with_handle(future::lazy(move |_| job.process()))).unwrap();
-            self.pending.push(Box::new(spawned));
+            let sender = self.sender.clone();
+
+            self.pool.execute(move || {
+                let result =
+                    panic::catch_unwind(AssertUnwindSafe(|| job.process()))
+                    .unwrap_or_else(|_| {
+                        Err(::Error::handler("the job panicked").in_rule(name.clone()))
+                    });
+
+                let _ = sender.send((name, result));
+            });
+
+            self.pending += 1;
         }
     }
 }
+
+/// Enumerate the files in the input directory.
+///
+/// Files and directories whose name matches `Configuration::ignore`
+/// are skipped, as are those ignored by `.diecastignore` files, and
+/// by `.gitignore` files if `Configuration::use_gitignore` is set.
+/// The `.diecastignore` files themselves are skipped as well.
+pub fn enumerate(configuration: &Configuration) -> Vec<PathBuf> {
+    use walkdir::WalkDir;
+
+    // the rules of the ignore files of the directories that contain
+    // the entry being walked, along with the depth of each directory
+    let mut scopes: Vec<(usize, Rules)> = vec![];
+
+    WalkDir::new(&configuration.input)
+        .into_iter()
+        .filter_entry(|entry| {
+            let file_name = Path::new(entry.path().file_name().unwrap());
+
+            if let Some(ref ignore) = configuration.ignore {
+                if ignore.matches(file_name) {
+                    return false;
+                }
+            }
+
+            // directories are walked before their contents
+            while scopes.last().is_some_and(|&(depth, _)| depth >= entry.depth()) {
+                scopes.pop();
+            }
+
+            let relative = entry.path().strip_prefix(&configuration.input).unwrap();
+            let is_dir = entry.file_type().is_dir();
+
+            if entry.depth() > 0 {
+                if file_name == Path::new(ignore::FILE_NAME) && !is_dir {
+                    return false;
+                }
+
+                if ignore::is_ignored(scopes.iter().map(|(_, rules)| rules), relative, is_dir) {
+                    return false;
+                }
+            }
+
+            if is_dir {
+                let rules = Rules::load(entry.path(), relative, configuration.use_gitignore);
+
+                if !rules.is_empty() {
+                    scopes.push((entry.depth(), rules));
+                }
+            }
+
+            true
+        })
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            entry.metadata()
+                .map(|m| {
+                    if m.is_file() { Some(entry.path().to_path_buf()) }
+                    else { None }
+                })
+                .unwrap_or(None)
+        })
+        .collect()
+}
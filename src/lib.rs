@@ -19,7 +19,10 @@ extern crate serde;
 
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_path_to_error;
 
+#[allow(unused_imports)]
 #[macro_use]
 extern crate log;
 
@@ -28,21 +31,21 @@ extern crate docopt;
 extern crate num_cpus;
 extern crate ansi_term;
 
-extern crate futures;
-
 pub use pattern::Pattern;
 pub use site::Site;
 pub use rule::Rule;
 pub use configuration::Configuration;
-pub use item::Item;
+pub use item::{Item, Body};
 pub use bind::Bind;
 pub use handler::Handle;
 // TODO command hooks
 pub use command::Command;
+pub use error::Error;
 
 mod handler;
 mod job;
 mod dependency;
+mod cache;
 
 #[macro_use]
 pub mod macros;
@@ -55,6 +58,8 @@ pub mod command;
 pub mod configuration;
 pub mod util;
 pub mod support;
+pub mod error;
+pub mod event;
+pub mod graph;
 
-pub type Error = Box<::std::error::Error + Sync + Send>;
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -17,6 +17,14 @@
 //!            not!("pages/secret-work.md")))
 //!);
 //!```
+//!
+//! Patterns can also be parsed from text with `parse`, e.g. from the
+//! configuration file. The `&`, `|` and `!` operators combine globs
+//! and `re:` regular expressions, and parentheses group them:
+//!
+//!```ignore
+//!let pat = pattern::parse("posts/**/*.md & !drafts/** | pages/*.md")?;
+//!```
 
 use glob;
 use regex::Regex;
@@ -25,27 +33,50 @@ use std::collections::HashSet;
 
 /// A kind of pattern that can be used for
 /// filtering the files in the input directory.
+#[allow(anonymous_parameters)]
 pub trait Pattern {
     fn matches(&self, &Path) -> bool;
+
+    /// The textual form of the pattern as understood by `parse`,
+    /// if it has one.
+    ///
+    /// This allows the pattern to be persisted, e.g. in the build cache.
+    fn describe(&self) -> Option<String> {
+        None
+    }
 }
 
-impl<P> Pattern for Box<P>
+impl<P: ?Sized> Pattern for Box<P>
 where P: Pattern {
     fn matches(&self, path: &Path) -> bool {
         (**self).matches(path)
     }
+
+    fn describe(&self) -> Option<String> {
+        (**self).describe()
+    }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a, P: ?Sized> Pattern for &'a P where P: Pattern {
     fn matches(&self, path: &Path) -> bool {
         (**self).matches(path)
     }
+
+    fn describe(&self) -> Option<String> {
+        (**self).describe()
+    }
 }
 
+#[allow(clippy::needless_lifetimes)]
 impl<'a, P: ?Sized> Pattern for &'a mut P where P: Pattern {
     fn matches(&self, path: &Path) -> bool {
         (**self).matches(path)
     }
+
+    fn describe(&self) -> Option<String> {
+        (**self).describe()
+    }
 }
 
 /// The negation of a pattern.
@@ -59,22 +90,11 @@ where P: Pattern {
     fn matches(&self, p: &Path) -> bool {
         !self.pattern.matches(p)
     }
-}
 
-// TODO
-// can create an And and Or that are not type parameterized
-// they would store trait objects in a vec
-//
-// And's implementation would be self.objs.iter().all(|p| p.matches(p))
-// Or's implementation would be self.objs.iter().any(|p| p.matches(p))
-//
-// Would be (with appropriate macro):
-//
-// Or::new().add(pat1).add(pat2)
-//
-// Or just introduce or! etc. macro which expands to
-//
-// Or::from(vec![Box::new(pat1) as Box<Pattern>, Box::new(pat2) as Box<Pattern>])
+    fn describe(&self) -> Option<String> {
+        self.pattern.describe().map(|pattern| format!("!({})", pattern))
+    }
+}
 
 /// This conjunction of two patterns.
 pub struct And<A, B>
@@ -88,6 +108,10 @@ where A: Pattern, B: Pattern {
     fn matches(&self, p: &Path) -> bool {
         self.left.matches(p) && self.right.matches(p)
     }
+
+    fn describe(&self) -> Option<String> {
+        combine(&[&self.left, &self.right], " & ")
+    }
 }
 
 /// The disjunction of two patterns.
@@ -102,6 +126,110 @@ where A: Pattern, B: Pattern {
     fn matches(&self, p: &Path) -> bool {
         self.left.matches(p) || self.right.matches(p)
     }
+
+    fn describe(&self) -> Option<String> {
+        combine(&[&self.left, &self.right], " | ")
+    }
+}
+
+/// The disjunction of any number of patterns.
+///
+/// Unlike `Or`, the patterns can be determined at runtime.
+/// Matches nothing if there are no patterns.
+pub struct AnyOf {
+    patterns: Vec<Box<dyn Pattern + Sync + Send>>,
+}
+
+impl AnyOf {
+    pub fn new() -> AnyOf {
+        AnyOf {
+            patterns: vec![],
+        }
+    }
+
+    pub fn with<P>(mut self, pattern: P) -> AnyOf
+    where P: Pattern + Sync + Send + 'static {
+        self.patterns.push(Box::new(pattern));
+        self
+    }
+}
+
+impl Default for AnyOf {
+    fn default() -> AnyOf {
+        AnyOf::new()
+    }
+}
+
+impl From<Vec<Box<dyn Pattern + Sync + Send>>> for AnyOf {
+    fn from(patterns: Vec<Box<dyn Pattern + Sync + Send>>) -> AnyOf {
+        AnyOf {
+            patterns,
+        }
+    }
+}
+
+impl Pattern for AnyOf {
+    fn matches(&self, p: &Path) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(p))
+    }
+
+    fn describe(&self) -> Option<String> {
+        if self.patterns.is_empty() {
+            return Nothing.describe();
+        }
+
+        combine(&self.patterns.iter().map(|p| p as &dyn Pattern).collect::<Vec<_>>(), " | ")
+    }
+}
+
+/// The conjunction of any number of patterns.
+///
+/// Unlike `And`, the patterns can be determined at runtime.
+/// Matches everything if there are no patterns.
+pub struct AllOf {
+    patterns: Vec<Box<dyn Pattern + Sync + Send>>,
+}
+
+impl AllOf {
+    pub fn new() -> AllOf {
+        AllOf {
+            patterns: vec![],
+        }
+    }
+
+    pub fn with<P>(mut self, pattern: P) -> AllOf
+    where P: Pattern + Sync + Send + 'static {
+        self.patterns.push(Box::new(pattern));
+        self
+    }
+}
+
+impl Default for AllOf {
+    fn default() -> AllOf {
+        AllOf::new()
+    }
+}
+
+impl From<Vec<Box<dyn Pattern + Sync + Send>>> for AllOf {
+    fn from(patterns: Vec<Box<dyn Pattern + Sync + Send>>) -> AllOf {
+        AllOf {
+            patterns,
+        }
+    }
+}
+
+impl Pattern for AllOf {
+    fn matches(&self, p: &Path) -> bool {
+        self.patterns.iter().all(|pattern| pattern.matches(p))
+    }
+
+    fn describe(&self) -> Option<String> {
+        if self.patterns.is_empty() {
+            return Everything.describe();
+        }
+
+        combine(&self.patterns.iter().map(|p| p as &dyn Pattern).collect::<Vec<_>>(), " & ")
+    }
 }
 
 /// Pattern that matches everything.
@@ -112,6 +240,10 @@ impl Pattern for Everything {
     fn matches(&self, _: &Path) -> bool {
         true
     }
+
+    fn describe(&self) -> Option<String> {
+        Some(String::from("re:^"))
+    }
 }
 
 /// Pattern that matches nothing.
@@ -122,27 +254,45 @@ impl Pattern for Nothing {
     fn matches(&self, _: &Path) -> bool {
         false
     }
+
+    fn describe(&self) -> Option<String> {
+        Some(String::from("!(re:^)"))
+    }
 }
 
 /// Allow regular expression patterns.
+#[allow(clippy::unnecessary_map_or)]
 impl Pattern for Regex {
     fn matches(&self, p: &Path) -> bool {
         p.to_str()
             .map_or(false, |s| self.is_match(s))
     }
+
+    fn describe(&self) -> Option<String> {
+        atom(format!("re:{}", self.as_str()))
+    }
 }
 
 /// Treat string slices as literal patterns.
+#[allow(clippy::unnecessary_map_or)]
 impl Pattern for str {
     fn matches(&self, p: &Path) -> bool {
         p.to_str().map_or(false, |s| self == s)
     }
+
+    fn describe(&self) -> Option<String> {
+        glob::Pattern::new(&glob::Pattern::escape(self)).ok()?.describe()
+    }
 }
 
 impl Pattern for Path {
     fn matches(&self, p: &Path) -> bool {
         self == p
     }
+
+    fn describe(&self) -> Option<String> {
+        self.to_str().and_then(Pattern::describe)
+    }
 }
 
 impl Pattern for HashSet<PathBuf> {
@@ -157,6 +307,198 @@ impl Pattern for glob::Pattern {
     fn matches(&self, p: &Path) -> bool {
         self.matches_path(p)
     }
+
+    fn describe(&self) -> Option<String> {
+        // it would be read back as a regular expression
+        if self.as_str().starts_with("re:") {
+            return None;
+        }
+
+        atom(String::from(self.as_str()))
+    }
+}
+
+/// The textual form of the patterns joined by the operator,
+/// if each of them has one.
+fn combine(patterns: &[&dyn Pattern], operator: &str) -> Option<String> {
+    patterns.iter()
+        .map(|pattern| pattern.describe().map(|text| format!("({})", text)))
+        .collect::<Option<Vec<String>>>()
+        .map(|patterns| patterns.join(operator))
+}
+
+/// The text of a single glob or regular expression, if `parse`
+/// reads it back as one.
+///
+/// It may not be, e.g. if it contains whitespace or an unbalanced
+/// parenthesis.
+fn atom(text: String) -> Option<String> {
+    let mut parser = Parser {
+        source: &text,
+        rest: &text,
+    };
+
+    let is_atom =
+        !text.starts_with('!')
+        && !text.starts_with('(')
+        && parser.atom().is_ok()
+        && parser.rest.is_empty();
+
+    if is_atom {
+        Some(text)
+    } else {
+        None
+    }
+}
+
+/// Parse a pattern from its textual form.
+///
+/// A pattern is a glob, e.g. `posts/**/*.md`, or a regular expression
+/// prefixed with `re:`, e.g. `re:\.md$`. Patterns are combined with
+/// `&` and `|`, negated with `!`, and grouped with parentheses. `!`
+/// binds tightest and `|` loosest, so `a & !b | c` is `(a & (!b)) | c`.
+///
+/// A glob or regular expression ends at whitespace or at an `&`, `|`
+/// or `)` that isn't within brackets or parentheses, so an alternation
+/// within a regular expression needs to be grouped, e.g. `re:(a|b)`.
+pub fn parse(source: &str) -> ::Result<Box<dyn Pattern + Sync + Send>> {
+    let mut parser = Parser {
+        source,
+        rest: source,
+    };
+
+    let pattern = parser.disjunction()?;
+
+    parser.skip_whitespace();
+
+    match parser.rest.chars().next() {
+        None => Ok(pattern),
+        Some(c) => Err(parser.error(&format!("unexpected `{}`", c))),
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> ::Error {
+        ::Error::Pattern(format!("{} at offset {} of {:?}",
+                                 message, self.source.len() - self.rest.len(), self.source))
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    /// Consume the operator if it's next.
+    fn eat(&mut self, operator: char) -> bool {
+        self.skip_whitespace();
+
+        match self.rest.strip_prefix(operator) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn disjunction(&mut self) -> ::Result<Box<dyn Pattern + Sync + Send>> {
+        let mut patterns = vec![self.conjunction()?];
+
+        while self.eat('|') {
+            patterns.push(self.conjunction()?);
+        }
+
+        Ok(if patterns.len() == 1 {
+            patterns.pop().unwrap()
+        } else {
+            Box::new(AnyOf::from(patterns))
+        })
+    }
+
+    fn conjunction(&mut self) -> ::Result<Box<dyn Pattern + Sync + Send>> {
+        let mut patterns = vec![self.negation()?];
+
+        while self.eat('&') {
+            patterns.push(self.negation()?);
+        }
+
+        Ok(if patterns.len() == 1 {
+            patterns.pop().unwrap()
+        } else {
+            Box::new(AllOf::from(patterns))
+        })
+    }
+
+    fn negation(&mut self) -> ::Result<Box<dyn Pattern + Sync + Send>> {
+        if self.eat('!') {
+            return Ok(Box::new(dsl::not(self.negation()?)));
+        }
+
+        if self.eat('(') {
+            let pattern = self.disjunction()?;
+
+            if !self.eat(')') {
+                return Err(self.error("expected `)`"));
+            }
+
+            return Ok(pattern);
+        }
+
+        self.atom()
+    }
+
+    fn atom(&mut self) -> ::Result<Box<dyn Pattern + Sync + Send>> {
+        self.skip_whitespace();
+
+        let mut depth = 0usize;
+        let mut in_brackets = false;
+        let mut escaped = false;
+        let mut end = self.rest.len();
+
+        for (idx, c) in self.rest.char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+
+            match c {
+                '\\' => escaped = true,
+                '[' if !in_brackets => in_brackets = true,
+                ']' if in_brackets => in_brackets = false,
+                _ if in_brackets => (),
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                '&' | '|' | ')' if depth == 0 => {
+                    end = idx;
+                    break;
+                },
+                c if c.is_whitespace() && depth == 0 => {
+                    end = idx;
+                    break;
+                },
+                _ => (),
+            }
+        }
+
+        let text = &self.rest[.. end];
+
+        if text.is_empty() {
+            return Err(self.error("expected a pattern"));
+        }
+
+        let pattern: Box<dyn Pattern + Sync + Send> = match text.strip_prefix("re:") {
+            Some(regex) => Box::new(Regex::new(regex)?),
+            None => Box::new(glob::Pattern::new(text)?),
+        };
+
+        self.rest = &self.rest[end ..];
+
+        Ok(pattern)
+    }
 }
 
 /// Contains the DSL items for easily constructing complex patterns.
@@ -194,10 +536,11 @@ pub mod dsl {
 mod test {
     use regex::Regex;
 
-    use super::{Pattern, Everything};
+    use super::{Pattern, Everything, AnyOf, AllOf};
     use std::path::Path;
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn match_everything() {
         let intro_to_rust = Path::new("posts/long/introduction-to-rust.md");
 
@@ -205,6 +548,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn match_globs() {
         use glob;
 
@@ -217,6 +561,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn match_regex() {
         let intro_to_rust = Path::new("posts/long/introduction-to-rust.md");
         let this_week_in_rust = Path::new("posts/short/this-week-in-rust.md");
@@ -226,6 +571,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn match_conjunctions() {
         use glob;
 
@@ -243,15 +589,24 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn match_disjunctions() {
         let intro_to_rust = Path::new("posts/long/introduction-to-rust.md");
         let about_page = Path::new("pages/about.md");
 
         assert!(or!("pages/about.md", "second.md").matches(&about_page));
         assert!(!or!("pages/about.md", "second.md").matches(&intro_to_rust));
+
+        let any = AnyOf::new().with("pages/about.md").with("second.md");
+
+        assert!(any.matches(about_page));
+        assert!(!any.matches(intro_to_rust));
+        assert!(!AnyOf::new().matches(about_page));
+        assert!(AllOf::new().matches(about_page));
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn not_match_negations() {
         let intro_to_rust = Path::new("posts/long/introduction-to-rust.md");
         let about_page = Path::new("pages/about.md");
@@ -261,6 +616,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn match_single_files() {
         let about_page = Path::new("pages/about.md");
 
@@ -268,6 +624,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn use_macros() {
         use glob;
 
@@ -298,4 +655,65 @@ mod test {
                           not!("posts/short/this-week-in-rust.md")))
                 .matches(&this_week_in_rust));
     }
+
+    #[test]
+    fn parse_patterns() {
+        use super::parse;
+
+        let intro_to_rust = Path::new("posts/long/introduction-to-rust.md");
+        let draft = Path::new("drafts/posts/unfinished.md");
+        let draft_post = Path::new("posts/drafts/unfinished.md");
+        let about_page = Path::new("pages/about.md");
+        let notes = Path::new("pages/notes.txt");
+
+        let pattern = parse("posts/**/*.md & !posts/drafts/** | pages/*.md").unwrap();
+
+        assert!(pattern.matches(intro_to_rust));
+        assert!(pattern.matches(about_page));
+        assert!(!pattern.matches(draft_post));
+        assert!(!pattern.matches(draft));
+        assert!(!pattern.matches(notes));
+
+        let grouped = parse("!(re:^(drafts|pages)/ | *.txt) & re:\\.md$").unwrap();
+
+        assert!(grouped.matches(intro_to_rust));
+        assert!(grouped.matches(draft_post));
+        assert!(!grouped.matches(draft));
+        assert!(!grouped.matches(about_page));
+
+        assert!(parse("pages/[!a]*.md").unwrap().matches(notes.with_extension("md").as_path()));
+
+        for invalid in &["", "posts/** &", "(posts/**", "posts/** )", "re:(", "a | | b"] {
+            assert!(parse(invalid).is_err(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn describe_patterns() {
+        use glob;
+        use super::{parse, AnyOf, Everything};
+
+        let intro_to_rust = Path::new("posts/long/introduction-to-rust.md");
+        let draft_post = Path::new("posts/drafts/unfinished.md");
+        let about_page = Path::new("pages/about.md");
+
+        let pattern =
+            or!(and!(glob::Pattern::new("posts/**").unwrap(),
+                     not!(Regex::new(r"^posts/drafts/").unwrap())),
+                "pages/about.md");
+
+        let parsed = parse(&pattern.describe().unwrap()).unwrap();
+
+        for path in &[intro_to_rust, draft_post, about_page] {
+            assert_eq!(parsed.matches(path), pattern.matches(path), "{:?}", path);
+        }
+
+        assert!(parse(&Everything.describe().unwrap()).unwrap().matches(about_page));
+        assert!(!parse(&AnyOf::new().describe().unwrap()).unwrap().matches(about_page));
+
+        // patterns that `parse` would read differently have no description
+        assert!(Regex::new(r"a b").unwrap().describe().is_none());
+        assert!(and!("pages/about.md", Regex::new(r"a b").unwrap()).describe().is_none());
+        assert!(glob::Pattern::new("re:literal").unwrap().describe().is_none());
+    }
 }
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 use std::collections::HashSet;
 use std::convert::Into;
+use std::panic::Location;
 
 use bind::Bind;
 use util;
@@ -9,16 +10,20 @@ use handler::Handle;
 #[must_use]
 pub struct Builder {
     name: String,
+    #[allow(bare_trait_objects)]
     handler: Arc<Handle<Bind> + Sync + Send>,
     dependencies: HashSet<String>,
+    location: &'static Location<'static>,
 }
 
 impl Builder {
-    fn new(name: String) -> Builder {
+    #[allow(clippy::redundant_field_names)]
+    fn new(name: String, location: &'static Location<'static>) -> Builder {
         Builder {
             name: name,
             handler: Arc::new(util::handle::bind::missing),
             dependencies: HashSet::new(),
+            location: location,
         }
     }
 
@@ -41,6 +46,7 @@ impl Builder {
             name: self.name,
             handler: self.handler,
             dependencies: self.dependencies,
+            location: self.location,
         }
     }
 }
@@ -51,16 +57,24 @@ impl Builder {
 /// it may have.
 pub struct Rule {
     name: String,
+    #[allow(bare_trait_objects)]
     handler: Arc<Handle<Bind> + Sync + Send>,
     dependencies: HashSet<String>,
+    location: &'static Location<'static>,
 }
 
 impl Rule {
+    /// Begin defining a rule.
+    ///
+    /// The location of the call is recorded so that errors
+    /// about the rule can point to its definition.
+    #[track_caller]
     pub fn named<N>(name: N) -> Builder
     where N: Into<String> {
-        Builder::new(name.into())
+        Builder::new(name.into(), Location::caller())
     }
 
+    #[allow(bare_trait_objects)]
     pub fn handler(&self) -> Arc<Handle<Bind> + Sync + Send> {
         self.handler.clone()
     }
@@ -72,8 +86,14 @@ impl Rule {
     pub fn dependencies(&self) -> &HashSet<String> {
         &self.dependencies
     }
+
+    /// Where the rule was defined.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
 }
 
+#[allow(clippy::from_over_into, clippy::needless_lifetimes)]
 impl<'a> Into<String> for &'a Rule {
     fn into(self) -> String {
         self.name.clone()
@@ -1,11 +1,17 @@
 //! Site generation.
 
 use std::sync::Arc;
-use std::collections::HashSet;
-use std::fs;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::{env, fs};
 
 use job;
+use cache::{self, Cache};
 use configuration::Configuration;
+use dependency::Graph;
+use event::{BuildEvent, Events, Observer};
+use graph::{self, RuleGraph};
 use rule::Rule;
 use support;
 
@@ -16,61 +22,596 @@ use support;
 pub struct Site {
     configuration: Configuration,
     rules: Vec<Arc<Rule>>,
+
+    /// The scheduler of the last build, retained so that
+    /// subsequent rebuilds can reuse its finished binds.
+    scheduler: Option<job::Scheduler>,
+
+    /// Where build events are published.
+    events: Events,
 }
 
 impl Site {
+    /// Construct a site from its rules.
+    ///
+    /// The rules' dependencies are validated before building;
+    /// see `try_new` to validate them upfront.
     pub fn new(rules: Vec<Rule>) -> Site {
-        let mut site_rules = vec![];
+        Site {
+            configuration: Configuration::new(),
+            rules: rules.into_iter().map(Arc::new).collect(),
+            scheduler: None,
+            events: Events::default(),
+        }
+    }
 
+    /// Construct a site from its rules, ensuring that
+    /// their dependencies are valid.
+    ///
+    /// See `validate` for the errors that may occur.
+    pub fn try_new(rules: Vec<Rule>) -> ::Result<Site> {
+        let site = Site::new(rules);
+        site.validate()?;
+        Ok(site)
+    }
+
+    /// Ensure that every dependency of every rule is registered
+    /// and that the dependencies don't form a cycle.
+    ///
+    /// Every problem is reported, along with where the offending
+    /// rules were defined. Multiple problems are reported as an
+    /// `Error::Aggregate`.
+    pub fn validate(&self) -> ::Result<()> {
         let names =
-            rules.iter()
-            .map(|r| String::from(r.name()))
-            .collect::<HashSet<_>>();
-
-        for rule in rules {
-            if !rule.dependencies().is_empty() {
-                let diff: HashSet<_> =
-                    rule.dependencies().difference(&names).collect();
-
-                if !diff.is_empty() {
-                    println!("`{}` depends on unregistered rule(s) `{:?}`", rule.name(), diff);
-                    ::std::process::exit(1);
+            self.rules.iter()
+            .map(|rule| rule.name())
+            .collect::<BTreeSet<&str>>();
+
+        let mut errors =
+            self.rules.iter()
+            .filter_map(|rule| {
+                let mut unknown =
+                    rule.dependencies().iter()
+                    .filter(|dep| !names.contains(&dep[..]))
+                    .cloned()
+                    .collect::<Vec<String>>();
+
+                if unknown.is_empty() {
+                    return None;
                 }
-            }
 
-            site_rules.push(Arc::new(rule));
+                unknown.sort();
+
+                Some(::Error::UnknownDependencies {
+                    rule: String::from(rule.name()),
+                    location: Some(rule.location()),
+                    dependencies: unknown,
+                })
+            })
+            .collect::<Vec<::Error>>();
+
+        let locations =
+            self.rules.iter()
+            .map(|rule| (rule.name(), rule.location()))
+            .collect::<BTreeMap<&str, _>>();
+
+        // unknown dependencies are nodes without dependencies
+        // of their own, so they can't be part of a cycle
+        for cycle in self.graph().cycles() {
+            errors.push(::Error::Cycle(
+                cycle.cycle().iter()
+                .map(|name| (name.clone(), locations.get(&name[..]).cloned()))
+                .collect()));
         }
 
-        Site {
-            configuration: Configuration::new(),
-            rules: site_rules,
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(::Error::Aggregate { errors, skipped: vec![] }),
         }
     }
 
     pub fn build(&mut self) -> ::Result<()> {
-        self.clean()?;
+        self.build_selection(None)
+    }
+
+    /// Build the named rules along with every rule they transitively
+    /// depend on.
+    ///
+    /// The output directory isn't cleaned beforehand, so the output of
+    /// every other rule is left as it is.
+    pub fn build_rules<S>(&mut self, names: &[S]) -> ::Result<()>
+    where S: AsRef<str> {
+        let mut unknown =
+            names.iter()
+            .map(|name| name.as_ref())
+            .filter(|name| !self.rules.iter().any(|rule| rule.name() == *name))
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<String>>();
+
+        if !unknown.is_empty() {
+            unknown.sort();
+            unknown.dedup();
+
+            return Err(::Error::Configuration(
+                format!("there are no rules named {}", unknown.join(", "))));
+        }
+
+        let selection =
+            self.graph().dependencies_closure(
+                names.iter().map(|name| String::from(name.as_ref())).collect());
+
+        self.build_selection(Some(selection))
+    }
+
+    /// Build the selected rules, or every rule if there's no selection.
+    fn build_selection(&mut self, selection: Option<BTreeSet<String>>) -> ::Result<()> {
+        if self.configuration.is_atomic {
+            self.build_atomically(selection)
+        } else {
+            self.build_in_place(selection)
+        }
+    }
 
-        let mut scheduler = job::Scheduler::new(Arc::new(self.configuration.clone()));
+    /// Build into a staging directory that replaces the output
+    /// directory only if the build succeeds.
+    ///
+    /// The staging directory starts out as a copy of the output
+    /// directory, so that the build proceeds as it would in place. The
+    /// previous output directory is kept as a backup, replacing the
+    /// previous backup. If the build fails, the staging directory is
+    /// discarded and the output directory is left untouched.
+    ///
+    /// The paths that cleaning keeps, e.g. a `.git` directory, aren't
+    /// copied. They're moved from the backup into the new output
+    /// directory once it's in place, so they aren't in the backup.
+    ///
+    /// The two renames that swap the directories are each atomic, but
+    /// the output directory briefly doesn't exist between them. If the
+    /// second fails, the previous output directory is put back.
+    fn build_atomically(&mut self, selection: Option<BTreeSet<String>>) -> ::Result<()> {
+        let output = self.configuration.output.clone();
+        let staging = beside(&output, "staging")?;
+        let backup = beside(&output, "backup")?;
 
-        println!("building from {:?}", self.configuration.input);
+        self.protect_output("replace")?;
+
+        // left behind by an interrupted build
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+
+        let mut kept = vec![];
+
+        if output.exists() {
+            self.stage_directory(&output, &staging, Path::new(""), &mut kept)?;
+        }
+
+        self.configuration.output = staging.clone();
+        let result = self.build_in_place(selection);
+        self.configuration.output = output.clone();
+
+        // the retained binds would write to the staging directory
+        self.scheduler = None;
+
+        if let Err(e) = result {
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+
+            // the cache describes the discarded staging directory
+            if self.configuration.is_incremental {
+                Cache::invalidate(&self.configuration.cache)?;
+            }
+
+            return Err(e);
+        }
+
+        if backup.exists() {
+            fs::remove_dir_all(&backup)?;
+        }
+
+        if output.exists() {
+            fs::rename(&output, &backup)?;
+        }
+
+        if let Err(e) = fs::rename(&staging, &output) {
+            if backup.exists() {
+                fs::rename(&backup, &output)?;
+            }
+
+            return Err(::Error::Io(e));
+        }
+
+        for path in kept {
+            // a rule wrote it in the meantime
+            if output.join(&path).exists() {
+                continue;
+            }
+
+            if let Some(parent) = output.join(&path).parent() {
+                support::mkdir_p(parent)?;
+            }
+
+            fs::rename(backup.join(&path), output.join(&path))?;
+        }
+
+        Ok(())
+    }
+
+    /// Build the selected rules directly into the output directory.
+    fn build_in_place(&mut self, selection: Option<BTreeSet<String>>) -> ::Result<()> {
+        self.validate()?;
+
+        let cache =
+            if self.configuration.is_incremental {
+                Cache::load(&self.configuration.cache)?
+            } else {
+                None
+            };
+
+        // without a cache there's no telling which outputs are
+        // stale, so start from scratch, unless only some of the
+        // rules are being built. when pruning, the stale outputs
+        // are instead removed once every rule has written its own
+        let is_pruning =
+            self.configuration.is_pruning
+            && cache.is_none()
+            && selection.is_none();
+
+        if is_pruning {
+            self.protect_output("prune")?;
+        } else if cache.is_none() && selection.is_none() {
+            self.clean()?;
+        }
+
+        let start = Instant::now();
+
+        let mut scheduler = job::Scheduler::new(
+            Arc::new(self.configuration.clone()),
+            self.events.clone());
+
+        self.events.publish(BuildEvent::BuildStarted {
+            input: self.configuration.input.clone(),
+        });
 
         if !&self.configuration.input.exists() {
-            println!("the input directory `{:?}` does not exist!",
-                     self.configuration.input);
-            ::std::process::exit(1);
+            return Err(::Error::Configuration(
+                format!("the input directory {:?} does not exist",
+                        self.configuration.input)));
         }
 
         scheduler.update_paths();
 
+        let inputs =
+            if self.configuration.is_incremental {
+                cache::fingerprint(&self.configuration.input, &scheduler.paths())
+            } else {
+                cache::Fingerprints::new()
+            };
+
+        let scheduled = match cache {
+            Some(ref cache) => {
+                cache.outdated(&self.rules, &self.graph(), &inputs, &self.configuration.output)?
+            },
+            None => {
+                self.rules.iter()
+                .map(|rule| String::from(rule.name()))
+                .collect::<BTreeSet<String>>()
+            },
+        };
+
+        // the selection includes the dependencies of the selected
+        // rules, as do the outdated rules, so the intersection does too
+        let scheduled = match selection {
+            Some(selection) => scheduled.intersection(&selection).cloned().collect(),
+            None => scheduled,
+        };
+
         for rule in &self.rules {
-           // FIXME: this just seems weird re: strings
-           scheduler.add(rule.clone());
+            if scheduled.contains(rule.name()) {
+                // FIXME: this just seems weird re: strings
+                scheduler.add(rule.clone());
+            }
         }
 
         // create the output directory
         support::mkdir_p(&self.configuration.output).unwrap();
 
-        scheduler.build()
+        let mut result = scheduler.build();
+
+        // a failed rule may not have written everything it
+        // would have, so its previous output is left alone
+        if is_pruning && result.is_ok() {
+            match self.prune(&scheduler.written()) {
+                Ok(removed) => {
+                    for path in removed {
+                        self.events.publish(BuildEvent::OutputRemoved {
+                            path: self.configuration.output.join(path),
+                        });
+                    }
+                },
+                Err(e) => result = merge(result, e),
+            }
+        }
+
+        self.finish(scheduler, &scheduled, cache, inputs, start, result)
+    }
+
+    /// Check the site without touching the output directory.
+    ///
+    /// The rules are validated and then every rule is built into a
+    /// throwaway directory which is removed afterward, so that handler
+    /// errors surface without a destructive build. Every failure is
+    /// reported, as if building with `is_keep_going`. The cache and
+    /// the retained state used by `rebuild` are left as they are.
+    pub fn check(&mut self) -> ::Result<()> {
+        self.validate()?;
+
+        let start = Instant::now();
+
+        let mut configuration = self.configuration.clone();
+        configuration.is_incremental = false;
+        configuration.is_keep_going = true;
+
+        if !configuration.input.exists() {
+            return Err(::Error::Configuration(
+                format!("the input directory {:?} does not exist", configuration.input)));
+        }
+
+        self.events.publish(BuildEvent::BuildStarted {
+            input: configuration.input.clone(),
+        });
+
+        // removed once the check is done, even if a handler panics
+        let sink = support::TempDir::new("check")?;
+        configuration.output = sink.path().to_path_buf();
+
+        let mut scheduler = job::Scheduler::new(Arc::new(configuration), self.events.clone());
+
+        scheduler.update_paths();
+
+        for rule in &self.rules {
+            scheduler.add(rule.clone());
+        }
+
+        let result = scheduler.build();
+
+        let failures = match result {
+            Ok(()) => 0,
+            Err(::Error::Aggregate { ref errors, .. }) => errors.len(),
+            Err(_) => 1,
+        };
+
+        self.events.publish(BuildEvent::BuildComplete {
+            rules: scheduler.finished().len(),
+            failures,
+            duration: start.elapsed(),
+        });
+
+        result
+    }
+
+    /// Rebuild the rules affected by changes to the given paths.
+    ///
+    /// The paths are relative to the input directory. Only the rules that
+    /// selected any of the changed paths, and the rules that depend on
+    /// those, are built again. Every other rule retains its bind from the
+    /// previous build.
+    ///
+    /// The files that a rebuilt rule wrote the last time but no longer
+    /// writes, e.g. because their input was removed, are removed from
+    /// the output directory, unless the rule failed.
+    ///
+    /// Falls back to a full build if the site hasn't been built yet.
+    pub fn rebuild(&mut self, changed: &[PathBuf]) -> ::Result<()> {
+        if self.scheduler.is_none() {
+            return self.build();
+        }
+
+        // the scheduler is retained even if this fails, so that
+        // the next rebuild doesn't fall back to a full build
+        support::mkdir_p(&self.configuration.output)?;
+
+        let cache =
+            if self.configuration.is_incremental {
+                Cache::load(&self.configuration.cache)?
+            } else {
+                None
+            };
+
+        let mut scheduler = self.scheduler.take().unwrap();
+
+        let start = Instant::now();
+
+        self.events.publish(BuildEvent::BuildStarted {
+            input: self.configuration.input.clone(),
+        });
+
+        scheduler.update_paths();
+
+        let scheduled = match scheduler.affected(changed) {
+            Ok(scheduled) => scheduled,
+            Err(e) => {
+                self.scheduler = Some(scheduler);
+                return Err(e);
+            },
+        };
+
+        let inputs =
+            if self.configuration.is_incremental {
+                cache::fingerprint(&self.configuration.input, &scheduler.paths())
+            } else {
+                cache::Fingerprints::new()
+            };
+
+        let previous = scheduler.written();
+
+        scheduler.requeue(&scheduled);
+
+        let mut result = scheduler.build();
+
+        let written = scheduler.written();
+
+        for (path, rule) in previous {
+            let is_stale =
+                scheduled.contains(&rule)
+                && scheduler.finished().contains_key(&rule)
+                && !written.contains_key(&path);
+
+            let path = self.configuration.output.join(path);
+
+            if is_stale && path.is_file() {
+                match fs::remove_file(&path) {
+                    Ok(()) => {
+                        self.events.publish(BuildEvent::OutputRemoved {
+                            path,
+                        });
+                    },
+                    Err(e) => result = merge(result, ::Error::Io(e)),
+                }
+            }
+        }
+
+        self.finish(scheduler, &scheduled, cache, inputs, start, result)
+    }
+
+    /// Wrap up a build.
+    ///
+    /// This records the outcome in the cache when building incrementally,
+    /// publishes the completion of the build, and retains the scheduler
+    /// for subsequent rebuilds. Failing to update the cache fails the
+    /// build, but the rest is done regardless.
+    fn finish(&mut self,
+              scheduler: job::Scheduler,
+              scheduled: &BTreeSet<String>,
+              cache: Option<Cache>,
+              inputs: cache::Fingerprints,
+              start: Instant,
+              mut result: ::Result<()>)
+    -> ::Result<()> {
+        if self.configuration.is_incremental {
+            let mut cache = cache.unwrap_or_default();
+
+            let updated = cache.update(
+                &self.rules,
+                scheduled,
+                scheduler.finished(),
+                inputs,
+                &self.configuration.output);
+
+            match updated {
+                Ok(removed) => {
+                    for path in removed {
+                        self.events.publish(BuildEvent::OutputRemoved {
+                            path,
+                        });
+                    }
+                },
+                Err(e) => result = merge(result, e),
+            }
+
+            if let Err(e) = cache.save(&self.configuration.cache) {
+                result = merge(result, e);
+            }
+        }
+
+        // the statistics are only informative, so failing
+        // to record them shouldn't fail the build
+        if self.configuration.is_incremental || self.configuration.is_recording_statistics {
+            if let Err(e) = self.record_statistics(&scheduler) {
+                self.events.publish(BuildEvent::StatisticsFailed {
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        let failures = match result {
+            Ok(()) => 0,
+            Err(::Error::Aggregate { ref errors, .. }) => errors.len(),
+            Err(_) => 1,
+        };
+
+        self.events.publish(BuildEvent::BuildComplete {
+            rules: scheduled.iter().filter(|name| scheduler.finished().contains_key(*name)).count(),
+            failures,
+            duration: start.elapsed(),
+        });
+
+        self.scheduler = Some(scheduler);
+
+        result
+    }
+
+    /// Record the statistics of the rules that finished in the cache
+    /// directory, keeping those of the rules that didn't run.
+    fn record_statistics(&self, scheduler: &job::Scheduler) -> ::Result<()> {
+        let mut statistics = cache::load_statistics(&self.configuration.cache)?;
+
+        for (name, duration) in scheduler.durations() {
+            if let Some(bind) = scheduler.finished().get(name) {
+                statistics.insert(name.clone(), cache::Statistics {
+                    items: bind.items().len(),
+                    duration: *duration,
+                });
+            }
+        }
+
+        // forget rules that are no longer registered
+        statistics.retain(|name, _| self.rules.iter().any(|rule| rule.name() == name));
+
+        cache::save_statistics(&self.configuration.cache, &statistics)
+    }
+
+    /// The rule dependency graph, annotated with the statistics
+    /// recorded by the last build, if any.
+    pub fn rule_graph(&self) -> ::Result<RuleGraph> {
+        let statistics = cache::load_statistics(&self.configuration.cache)?;
+
+        let rules =
+            self.rules.iter()
+            .map(|rule| {
+                let mut dependencies =
+                    rule.dependencies().iter().cloned().collect::<Vec<String>>();
+
+                dependencies.sort();
+
+                graph::Node {
+                    name: String::from(rule.name()),
+                    dependencies,
+                    statistics: statistics.get(rule.name()).cloned(),
+                }
+            })
+            .collect();
+
+        Ok(RuleGraph::new(rules))
+    }
+
+    /// The dependency graph of the rules.
+    fn graph(&self) -> Graph<String> {
+        let mut graph = Graph::new();
+
+        for rule in &self.rules {
+            graph.add_node(String::from(rule.name()));
+
+            for dep in rule.dependencies() {
+                graph.add_edge(dep.clone(), String::from(rule.name()));
+            }
+        }
+
+        graph
+    }
+
+    /// Register an observer of build events.
+    pub fn observe<O>(&mut self, observer: O)
+    where O: Observer + Sync + Send + 'static {
+        self.events.observe(observer);
+    }
+
+    /// Access the build event observers, e.g. to remove the
+    /// default console observer.
+    pub fn events_mut(&mut self) -> &mut Events {
+        &mut self.events
     }
 
     pub fn configuration(&self) -> &Configuration {
@@ -81,14 +622,816 @@ impl Site {
         &mut self.configuration
     }
 
+    /// Clean out the output directory.
+    ///
+    /// Hidden files and directories at the top level of the output
+    /// directory are kept if `Configuration::ignore_hidden` is set, e.g.
+    /// to preserve a `.git` directory that the site is deployed from, as
+    /// are paths matched by `Configuration::keep`. The output directory
+    /// itself is only removed if nothing was kept.
+    ///
+    /// Refuses to clean an output directory that is or contains the
+    /// input directory or the current directory.
     pub fn clean(&self) -> ::Result<()> {
+        let output = &self.configuration.output;
+
         // output directory doesn't even exist; nothing to clean
-        if !&self.configuration.output.exists() {
+        if !output.exists() {
+            return Cache::invalidate(&self.configuration.cache);
+        }
+
+        self.protect_output("clean")?;
+
+        // the cache describes the output that's about to be removed
+        Cache::invalidate(&self.configuration.cache)?;
+
+        if self.clean_directory(output, Path::new(""), &BTreeMap::new(), &mut vec![])? {
+            fs::remove_dir(output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Refuse to remove files from an output directory that is or
+    /// contains the input directory or the current directory.
+    fn protect_output(&self, action: &str) -> ::Result<()> {
+        let output = &self.configuration.output;
+
+        if !output.exists() {
             return Ok(());
         }
 
-        fs::remove_dir_all(&self.configuration.output)
-            .map(|_| ())
-            .map_err(|e| Box::new(e) as ::Error)
+        let canonical = output.canonicalize()?;
+
+        let protected = vec![
+            ("input", self.configuration.input.clone()),
+            ("current", env::current_dir()?),
+        ];
+
+        for (name, directory) in protected {
+            let directory = directory.canonicalize().unwrap_or(directory);
+
+            if directory.starts_with(&canonical) {
+                return Err(::Error::Configuration(
+                    format!("refusing to {} the output directory {:?} \
+                             because it contains the {} directory {:?}",
+                            action, output, name, directory)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the files in the output directory that weren't written
+    /// during the build, along with the directories this empties.
+    ///
+    /// The paths that `clean` keeps are kept as well. Returns the
+    /// removed files, relative to the output directory.
+    fn prune(&self, written: &BTreeMap<PathBuf, String>) -> ::Result<Vec<PathBuf>> {
+        let mut removed = vec![];
+
+        self.clean_directory(&self.configuration.output, Path::new(""), written, &mut removed)?;
+
+        removed.sort();
+
+        Ok(removed)
+    }
+
+    /// Whether cleaning keeps a path within the output directory,
+    /// per `Configuration::ignore_hidden` and `Configuration::keep`.
+    fn is_kept(&self, path: &Path) -> bool {
+        let is_hidden =
+            path.parent() == Some(Path::new(""))
+            && path.to_string_lossy().starts_with('.');
+
+        (is_hidden && self.configuration.ignore_hidden)
+        || self.configuration.keep.as_ref().is_some_and(|keep| keep.matches(path))
+    }
+
+    /// Copy the contents of a directory within the output directory
+    /// into the staging directory, except for those that are kept.
+    ///
+    /// The kept paths are added to `kept` instead.
+    fn stage_directory(&self,
+                       directory: &Path,
+                       staging: &Path,
+                       relative: &Path,
+                       kept: &mut Vec<PathBuf>)
+    -> ::Result<()> {
+        support::mkdir_p(staging.join(relative))?;
+
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+
+            if self.is_kept(&path) {
+                kept.push(path);
+            } else if entry.path().is_dir() {
+                self.stage_directory(&entry.path(), staging, &path, kept)?;
+            } else {
+                fs::copy(entry.path(), staging.join(&path))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the contents of a directory within the output directory,
+    /// except for those that are kept or were written.
+    ///
+    /// The removed files are added to `removed`. Returns whether
+    /// everything was removed.
+    fn clean_directory(&self,
+                       directory: &Path,
+                       relative: &Path,
+                       written: &BTreeMap<PathBuf, String>,
+                       removed: &mut Vec<PathBuf>)
+    -> ::Result<bool> {
+        let mut emptied = true;
+
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+
+            if self.is_kept(&path) || written.contains_key(&path) {
+                emptied = false;
+                continue;
+            }
+
+            // don't follow symbolic links out of the output directory
+            if entry.file_type()?.is_dir() {
+                if self.clean_directory(&entry.path(), &path, written, removed)? {
+                    fs::remove_dir(entry.path())?;
+                } else {
+                    emptied = false;
+                }
+            } else {
+                fs::remove_file(entry.path())?;
+                removed.push(path);
+            }
+        }
+
+        Ok(emptied)
+    }
+}
+
+/// The path of a directory beside the output directory, named
+/// after it with the given suffix, e.g. `output.staging`.
+fn beside(output: &Path, suffix: &str) -> ::Result<PathBuf> {
+    let absolute =
+        if output.exists() {
+            output.canonicalize()?
+        } else {
+            env::current_dir()?.join(output)
+        };
+
+    match absolute.file_name() {
+        Some(name) => {
+            let mut name = name.to_os_string();
+            name.push(".");
+            name.push(suffix);
+
+            Ok(absolute.with_file_name(name))
+        },
+        None => Err(::Error::Configuration(
+            format!("the output directory {:?} has no name to put \
+                     a {} directory beside", output, suffix))),
+    }
+}
+
+/// Add an error to the result of a build, e.g. one that
+/// happened while wrapping it up.
+fn merge(result: ::Result<()>, error: ::Error) -> ::Result<()> {
+    match result {
+        Ok(()) => Err(error),
+        Err(::Error::Aggregate { mut errors, skipped }) => {
+            errors.push(error);
+            Err(::Error::Aggregate { errors, skipped })
+        },
+        Err(e) => Err(::Error::Aggregate { errors: vec![e, error], skipped: vec![] }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    use regex::Regex;
+
+    use bind::Bind;
+    use event::{BuildEvent, Events};
+    use item::Item;
+    use rule::Rule;
+    use support::TempDir;
+    use util::handle::item;
+    use super::Site;
+
+    /// A site of the given rules within a directory of its own, which
+    /// has an empty input directory and doesn't report any events.
+    ///
+    /// The directory is removed when the returned guard is dropped,
+    /// even if the test fails.
+    fn fixture(name: &str, rules: Vec<Rule>) -> (Site, TempDir) {
+        let dir = TempDir::new(name).unwrap();
+        let root = dir.path();
+
+        fs::create_dir_all(root.join("input")).unwrap();
+
+        let mut site = Site::new(rules);
+
+        *site.events_mut() = Events::new();
+
+        *site.configuration_mut() =
+            site.configuration().clone()
+            .input(root.join("input"))
+            .output(root.join("output"))
+            .cache(root.join("cache"));
+
+        (site, dir)
+    }
+
+    #[test]
+    fn validate_dependencies() {
+        let site = Site::new(vec![
+            Rule::named("posts").depends_on("templates").depends_on("tags").build(),
+            Rule::named("index").depends_on("posts").depends_on("feeds").build(),
+        ]);
+
+        match site.validate() {
+            Err(::Error::Aggregate { ref errors, .. }) => {
+                let messages = errors.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+
+                assert_eq!(messages.len(), 2);
+                assert!(messages[0].starts_with("rule `posts` (defined at src/site.rs:"));
+                assert!(messages[0].ends_with("depends on unregistered rule(s) `tags`, `templates`"));
+                assert!(messages[1].ends_with("depends on unregistered rule(s) `feeds`"));
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let cyclic = Site::try_new(vec![
+            Rule::named("a").depends_on("b").build(),
+            Rule::named("b").depends_on("a").build(),
+            Rule::named("c").depends_on("d").build(),
+            Rule::named("d").depends_on("c").build(),
+        ]);
+
+        match cyclic {
+            Err(::Error::Aggregate { ref errors, .. }) => {
+                assert_eq!(errors.len(), 2);
+
+                for error in errors {
+                    match *error {
+                        ::Error::Cycle(ref cycle) => {
+                            assert_eq!(cycle.len(), 3);
+                            assert!(cycle.iter().all(|&(_, location)| {
+                                location.map(|l| l.file()) == Some("src/site.rs")
+                            }));
+                        },
+                        ref other => panic!("unexpected error: {:?}", other),
+                    }
+                }
+            },
+            Err(other) => panic!("unexpected error: {:?}", other),
+            Ok(_) => panic!("the cycles weren't detected"),
+        }
+    }
+
+    #[test]
+    fn build_selected_rules() {
+        let built = Arc::new(Mutex::new(vec![]));
+
+        let rule = |name: &str| {
+            let built = built.clone();
+            let name = String::from(name);
+
+            Rule::named(name.clone())
+            .handler(move |_: &mut Bind| {
+                built.lock().unwrap().push(name.clone());
+                Ok(())
+            })
+        };
+
+        let (mut site, _temp) = fixture("build-rules", vec![
+            rule("templates").build(),
+            rule("posts").depends_on("templates").build(),
+            rule("pages").depends_on("templates").build(),
+        ]);
+
+        site.build_rules(&["posts"]).unwrap();
+
+        assert_eq!(*built.lock().unwrap(), vec!["templates", "posts"]);
+        assert!(site.build_rules(&["drafts"]).is_err());
+    }
+
+    #[test]
+    fn rebuild_rules_selecting_added_inputs() {
+        use util::handle::bind;
+
+        let built = Arc::new(Mutex::new(vec![]));
+
+        let rule = |name: &str, pattern: &str| {
+            let built = built.clone();
+            let name = String::from(name);
+
+            Rule::named(name.clone())
+            .handler(chain![
+                bind::select(glob!(pattern)),
+                move |_: &mut Bind| {
+                    built.lock().unwrap().push(name.clone());
+                    Ok(())
+                }])
+        };
+
+        let (mut site, temp) = fixture("added", vec![
+            rule("posts", "posts/*.md").build(),
+            rule("pages", "pages/*.md").build(),
+            rule("index", "index.md").depends_on("posts").build(),
+        ]);
+
+        let root = temp.path();
+
+        for dir in &["input/posts", "input/pages"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        fs::File::create(root.join("input/posts/a.md")).unwrap();
+        fs::File::create(root.join("input/pages/about.md")).unwrap();
+
+        site.configuration_mut().is_incremental = true;
+
+        site.build().unwrap();
+        assert_eq!(built.lock().unwrap().len(), 3);
+
+        built.lock().unwrap().clear();
+        fs::File::create(root.join("input/posts/b.md")).unwrap();
+
+        site.build().unwrap();
+
+        // `index` depends on `posts`, but `pages` is left alone
+        let mut rebuilt = built.lock().unwrap().clone();
+        rebuilt.sort();
+
+        assert_eq!(rebuilt, vec!["index", "posts"]);
+
+        built.lock().unwrap().clear();
+        fs::File::create(root.join("input/index.md")).unwrap();
+
+        site.build().unwrap();
+
+        // `posts` is run again for its bind, which `index` needs
+        let mut rebuilt = built.lock().unwrap().clone();
+        rebuilt.sort();
+
+        assert_eq!(rebuilt, vec!["index", "posts"]);
+    }
+
+    #[test]
+    fn rebuild_removed_inputs() {
+        use std::path::PathBuf;
+
+        use util::handle::bind;
+        use util::route;
+
+        let (mut site, temp) = fixture("removed", vec![
+            Rule::named("posts")
+            .handler(chain![
+                bind::select(glob!("posts/*.md")),
+                bind::each(chain![route::identity, item::copy])])
+            .build(),
+        ]);
+
+        let root = temp.path();
+        let output = root.join("output");
+
+        fs::create_dir_all(root.join("input/posts")).unwrap();
+
+        for file in &["a.md", "b.md"] {
+            fs::File::create(root.join("input/posts").join(file)).unwrap();
+        }
+
+        site.build().unwrap();
+        assert!(output.join("posts/b.md").exists());
+
+        fs::remove_file(root.join("input/posts/b.md")).unwrap();
+        site.rebuild(&[PathBuf::from("posts/b.md")]).unwrap();
+
+        assert!(output.join("posts/a.md").exists());
+        assert!(!output.join("posts/b.md").exists());
+    }
+
+    #[test]
+    fn retain_scheduler_after_failed_rebuild() {
+        use std::io::Write;
+        use std::path::PathBuf;
+
+        use util::handle::bind;
+
+        let built = Arc::new(Mutex::new(vec![]));
+
+        let rule = |name: &str, pattern: &str| {
+            let built = built.clone();
+            let name = String::from(name);
+
+            Rule::named(name.clone())
+            .handler(chain![
+                bind::select(glob!(pattern)),
+                move |_: &mut Bind| {
+                    built.lock().unwrap().push(name.clone());
+                    Ok(())
+                }])
+        };
+
+        let (mut site, temp) = fixture("retain", vec![
+            rule("posts", "posts/*.md").build(),
+            rule("pages", "pages/*.md").build(),
+        ]);
+
+        let root = temp.path();
+
+        for dir in &["input/posts", "input/pages"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        fs::File::create(root.join("input/posts/a.md")).unwrap();
+        fs::File::create(root.join("input/pages/about.md")).unwrap();
+
+        site.configuration_mut().is_incremental = true;
+        site.build().unwrap();
+
+        let cache = root.join("cache/build.toml");
+        fs::File::create(&cache).unwrap().write_all(b"not toml").unwrap();
+
+        let changed = [PathBuf::from("posts/a.md")];
+
+        built.lock().unwrap().clear();
+        assert!(site.rebuild(&changed).is_err());
+
+        // the next rebuild isn't a full build
+        fs::remove_file(&cache).unwrap();
+        site.rebuild(&changed).unwrap();
+
+        assert_eq!(*built.lock().unwrap(), vec!["posts"]);
+    }
+
+    #[test]
+    fn record_statistics_on_request() {
+        use std::io::Write;
+
+        let (mut site, temp) = fixture("statistics", vec![
+            Rule::named("pages").handler(|_: &mut Bind| Ok(())).build(),
+        ]);
+
+        let cache = temp.path().join("cache");
+        let failures = Arc::new(Mutex::new(vec![]));
+
+        {
+            let failures = failures.clone();
+
+            site.observe(move |event: &BuildEvent| {
+                if let BuildEvent::StatisticsFailed { ref error } = *event {
+                    failures.lock().unwrap().push(error.clone());
+                }
+            });
+        }
+
+        site.build().unwrap();
+        assert!(!cache.exists());
+
+        site.configuration_mut().is_recording_statistics = true;
+
+        fs::create_dir_all(&cache).unwrap();
+        fs::File::create(cache.join("statistics.toml")).unwrap()
+            .write_all(b"not toml").unwrap();
+
+        // a corrupt statistics file doesn't fail the build
+        site.build().unwrap();
+        assert_eq!(failures.lock().unwrap().len(), 1);
+
+        fs::remove_file(cache.join("statistics.toml")).unwrap();
+        site.build().unwrap();
+
+        let graph = site.rule_graph().unwrap();
+        assert_eq!(graph.rules()[0].statistics.map(|s| s.items), Some(0));
+    }
+
+    #[test]
+    fn check_in_throwaway_directory() {
+        let sinks = Arc::new(Mutex::new(vec![]));
+
+        let (mut site, temp) = {
+            let sinks = sinks.clone();
+
+            fixture("check", vec![
+                Rule::named("pages")
+                .handler(move |bind: &mut Bind| {
+                    sinks.lock().unwrap().push(bind.configuration.output.clone());
+                    Err(::Error::handler("broken"))
+                })
+                .build(),
+            ])
+        };
+
+        // the failure is returned rather than masked by the clean up
+        assert!(site.check().is_err());
+
+        let sinks = sinks.lock().unwrap();
+        assert_eq!(sinks.len(), 1);
+        assert!(!sinks[0].exists());
+        assert!(!temp.path().join("output").exists());
+    }
+
+    #[test]
+    fn clean_safely() {
+        let (mut site, temp) = fixture("clean", vec![]);
+        let output = temp.path().join("output");
+
+        for dir in &[".git/objects", "static", "posts"] {
+            fs::create_dir_all(output.join(dir)).unwrap();
+        }
+
+        for file in &[".git/HEAD", ".nojekyll", "CNAME", "static/kept.css", "posts/a.html", "index.html"] {
+            fs::File::create(output.join(file)).unwrap();
+        }
+
+        *site.configuration_mut() =
+            site.configuration().clone()
+            .ignore_hidden(true)
+            .keep(Regex::new("^CNAME$|^static/kept").unwrap());
+
+        site.clean().unwrap();
+
+        for kept in &[".git/HEAD", ".nojekyll", "CNAME", "static/kept.css"] {
+            assert!(output.join(kept).exists(), "{} was removed", kept);
+        }
+
+        for removed in &["posts", "index.html"] {
+            assert!(!output.join(removed).exists(), "{} was kept", removed);
+        }
+
+        // the input directory is within the output directory
+        site.configuration_mut().input = output.join("input");
+        fs::create_dir_all(output.join("input")).unwrap();
+        assert!(site.clean().is_err());
+        assert!(output.join("input").exists());
+    }
+
+    #[test]
+    fn prune_stale_output() {
+        let (mut site, temp) = fixture("prune", vec![
+            Rule::named("pages")
+            .handler(|bind: &mut Bind| {
+                bind.attach(Item::writing("index.html"));
+                bind.attach(Item::writing("posts/a.html"));
+
+                for item in bind.items_mut() {
+                    item::write(item)?;
+                }
+
+                Ok(())
+            })
+            .build(),
+        ]);
+
+        let output = temp.path().join("output");
+
+        for dir in &[".git", "posts", "drafts"] {
+            fs::create_dir_all(output.join(dir)).unwrap();
+        }
+
+        for file in &[".git/HEAD", "posts/a.html", "posts/old.html", "drafts/b.html", "old.html"] {
+            fs::File::create(output.join(file)).unwrap();
+        }
+
+        let removed = Arc::new(Mutex::new(vec![]));
+
+        {
+            let removed = removed.clone();
+
+            site.observe(move |event: &BuildEvent| {
+                if let BuildEvent::OutputRemoved { ref path } = *event {
+                    removed.lock().unwrap().push(path.clone());
+                }
+            });
+        }
+
+        *site.configuration_mut() =
+            site.configuration().clone()
+            .ignore_hidden(true)
+            .prune(true);
+
+        site.build().unwrap();
+
+        for kept in &[".git/HEAD", "index.html", "posts/a.html"] {
+            assert!(output.join(kept).exists(), "{} was removed", kept);
+        }
+
+        for pruned in &["posts/old.html", "drafts", "old.html"] {
+            assert!(!output.join(pruned).exists(), "{} was kept", pruned);
+        }
+
+        assert_eq!(*removed.lock().unwrap(), vec![
+            output.join("drafts/b.html"),
+            output.join("old.html"),
+            output.join("posts/old.html"),
+        ]);
+    }
+
+    #[test]
+    fn build_atomically() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let fail = Arc::new(AtomicBool::new(true));
+        let staged_kept = Arc::new(AtomicBool::new(false));
+
+        let (mut site, temp) = {
+            let fail = fail.clone();
+            let staged_kept = staged_kept.clone();
+
+            fixture("atomic", vec![
+                Rule::named("pages")
+                .handler(move |bind: &mut Bind| {
+                    if bind.configuration.output.join(".git").exists() {
+                        staged_kept.store(true, Ordering::SeqCst);
+                    }
+
+                    bind.attach(Item::writing("index.html"));
+
+                    for item in bind.items_mut() {
+                        item::write(item)?;
+                    }
+
+                    if fail.load(Ordering::SeqCst) {
+                        Err(::Error::from("pages failed"))
+                    } else {
+                        Ok(())
+                    }
+                })
+                .build(),
+            ])
+        };
+
+        let root = temp.path();
+        let output = root.join("output");
+
+        fs::create_dir_all(output.join(".git")).unwrap();
+
+        for file in &[".git/HEAD", "old.html"] {
+            fs::File::create(output.join(file)).unwrap();
+        }
+
+        *site.configuration_mut() =
+            site.configuration().clone()
+            .ignore_hidden(true)
+            .atomic(true);
+
+        // a failed build leaves the output as it was
+        assert!(site.build().is_err());
+        assert!(output.join("old.html").exists());
+        assert!(!output.join("index.html").exists());
+        assert!(!root.join("output.staging").exists());
+
+        fail.store(false, Ordering::SeqCst);
+        site.build().unwrap();
+
+        for kept in &[".git/HEAD", "index.html"] {
+            assert!(output.join(kept).exists(), "{} is missing", kept);
+        }
+
+        assert!(!output.join("old.html").exists());
+        assert!(root.join("output.backup/old.html").exists());
+        assert!(!root.join("output.staging").exists());
+
+        // kept paths are moved into place rather than copied
+        assert!(!staged_kept.load(Ordering::SeqCst));
+        assert!(!root.join("output.backup/.git").exists());
+    }
+
+    #[test]
+    fn detect_collisions() {
+        use std::thread;
+        use std::time::Duration;
+        use configuration::Collisions;
+
+        // the rules are independent, so they run and write concurrently
+        let rule = |name: &'static str, delay: u64| {
+            Rule::named(name)
+            .handler(move |bind: &mut Bind| {
+                thread::sleep(Duration::from_millis(delay));
+
+                let mut index = Item::writing("index.html");
+                index.body = name.into();
+                bind.attach(index);
+
+                for item in bind.items_mut() {
+                    item::write(item)?;
+                }
+
+                Ok(())
+            })
+        };
+
+        // the earlier rule writes last, yet its claim holds
+        let (mut site, temp) = fixture("collisions", vec![
+            rule("pages", 20).build(),
+            rule("index", 0).build(),
+        ]);
+
+        let warnings = Arc::new(Mutex::new(vec![]));
+
+        {
+            let warnings = warnings.clone();
+
+            site.observe(move |event: &BuildEvent| {
+                if let BuildEvent::OutputCollision { ref claims, .. } = *event {
+                    warnings.lock().unwrap().push(claims.clone());
+                }
+            });
+        }
+
+        site.configuration_mut().threads = 2;
+
+        for _ in 0..3 {
+            match site.build() {
+                Err(ref error) => {
+                    assert_eq!(error.rule(), Some("index"));
+
+                    match *error.kind() {
+                        ::Error::Collision { ref path, ref claims } => {
+                            assert_eq!(path.to_str(), Some("index.html"));
+
+                            let rules = claims.iter().map(|c| &c.rule[..]).collect::<Vec<&str>>();
+                            assert_eq!(rules, vec!["pages", "index"]);
+                        },
+                        ref other => panic!("unexpected error: {:?}", other),
+                    }
+                },
+                Ok(()) => panic!("the collision wasn't detected"),
+            }
+        }
+
+        site.configuration_mut().collisions = Collisions::Warn;
+
+        for _ in 0..3 {
+            site.build().unwrap();
+
+            let written = fs::read_to_string(temp.path().join("output/index.html")).unwrap();
+            assert_eq!(written, "pages");
+        }
+
+        {
+            let warnings = warnings.lock().unwrap();
+            assert_eq!(warnings.len(), 3);
+
+            for claims in warnings.iter() {
+                let rules = claims.iter().map(|c| &c.rule[..]).collect::<Vec<&str>>();
+                assert_eq!(rules, vec!["pages", "index"]);
+            }
+        }
+    }
+
+    #[test]
+    fn keep_going_after_collisions() {
+        let rule = |name: &str| {
+            Rule::named(name)
+            .handler(|bind: &mut Bind| {
+                bind.attach(Item::writing("index.html"));
+                bind.attach(Item::writing("about.html"));
+
+                for item in bind.items_mut() {
+                    item::write(item)?;
+                }
+
+                Ok(())
+            })
+        };
+
+        let (mut site, _temp) = fixture("keep-going", vec![
+            rule("pages").build(),
+            rule("index").depends_on("pages").build(),
+            Rule::named("feed").depends_on("index").build(),
+        ]);
+
+        site.configuration_mut().is_keep_going = true;
+
+        match site.build() {
+            Err(::Error::Aggregate { ref errors, ref skipped }) => {
+                // one error for each path, rather than one nested aggregate
+                assert_eq!(errors.len(), 2);
+
+                for error in errors {
+                    assert_eq!(error.rule(), Some("index"));
+
+                    match *error.kind() {
+                        ::Error::Collision { .. } => (),
+                        ref other => panic!("unexpected error: {:?}", other),
+                    }
+                }
+
+                assert_eq!(*skipped, vec![String::from("feed")]);
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 }
@@ -1,9 +1,16 @@
+use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::io;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde::Serializer;
 
 // TODO
 // remove this and use create_dir_all?
+#[allow(clippy::needless_return)]
 pub fn mkdir_p<P: AsRef<Path>>(path: P) -> io::Result<()> {
     let path = path.as_ref();
     if path == Path::new("") || ::std::fs::metadata(path).map(|m| m.is_dir()).unwrap_or(false) { return Ok(()) }
@@ -22,6 +29,64 @@ pub fn mkdir_p<P: AsRef<Path>>(path: P) -> io::Result<()> {
     }
 }
 
+/// Lexically normalize a relative path, resolving `.` and `..`
+/// components without consulting the file system.
+///
+/// Returns `None` if the path is absolute or if it
+/// escapes the directory it's relative to.
+pub fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            },
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(normalized)
+}
+
+/// A directory within the system's temporary directory that is
+/// removed along with its contents when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a directory whose name starts with the prefix and is
+    /// unique to the process and to each call.
+    pub fn new(prefix: &str) -> io::Result<TempDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "diecast-{}-{}-{}", prefix, process::id(), COUNT.fetch_add(1, Ordering::SeqCst)));
+
+        mkdir_p(&path)?;
+
+        Ok(TempDir {
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // there's nowhere to report a failure to clean up
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
 pub fn slugify(s: &str) -> String {
     s.chars()
     .filter_map(|c| {
@@ -36,3 +101,14 @@ pub fn slugify(s: &str) -> String {
     })
     .collect()
 }
+
+/// A duration in fractional seconds.
+pub fn seconds(duration: &Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// Serialize a duration in fractional seconds.
+pub fn serialize_seconds<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where S: Serializer {
+    serializer.serialize_f64(seconds(duration))
+}
@@ -1,17 +1,15 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::any::Any;
 use std::path::PathBuf;
 use std::{cmp, mem};
 
 use typemap;
 
-use futures::prelude::*;
-use futures::{self, future, Future};
-
-use item::Item;
+use item::{Claims, Item};
 use bind::Bind;
 use handler::Handle;
 use pattern::Pattern;
+use job::pool::Pool;
 
 use super::Extender;
 
@@ -21,6 +19,36 @@ impl typemap::Key for InputPaths {
     type Value = Arc<Vec<PathBuf>>;
 }
 
+/// The worker pool of the build.
+///
+/// `bind::each` processes the items on it.
+pub struct WorkerPool;
+
+impl typemap::Key for WorkerPool {
+    type Value = Arc<Pool>;
+}
+
+/// The claims on the paths written to the output directory.
+///
+/// Every bind of a build shares the same claims. They're made by
+/// `Item::write_target`, which `item::write` and `item::copy` use,
+/// and by `Item::mark_written`.
+pub struct OutputPaths;
+
+impl typemap::Key for OutputPaths {
+    type Value = Arc<Mutex<Claims>>;
+}
+
+/// The patterns used by `bind::select` to populate the bind.
+///
+/// This allows the scheduler to determine which rules are
+/// affected when files in the input directory change.
+pub struct Selections;
+
+impl typemap::Key for Selections {
+    type Value = Vec<Arc<dyn Pattern + Sync + Send>>;
+}
+
 impl<T> Handle<Bind> for Extender<T>
 where T: typemap::Key, T::Value: Any + Sync + Send + Clone {
     fn handle(&self, bind: &mut Bind) -> ::Result<()> {
@@ -43,7 +71,7 @@ impl Handle<Bind> for Create {
 
 pub struct Select<P>
 where P: Pattern + Sync + Send + 'static {
-    pattern: P,
+    pattern: Arc<P>,
 }
 
 impl<P> Handle<Bind> for Select<P>
@@ -51,6 +79,11 @@ where P: Pattern + Sync + Send + 'static {
     fn handle(&self, bind: &mut Bind) -> ::Result<()> {
         let paths = bind.extensions.read().unwrap().get::<InputPaths>().unwrap().clone();
 
+        bind.extensions.write().unwrap()
+            .entry::<Selections>()
+            .or_insert_with(Vec::new)
+            .push(self.pattern.clone() as Arc<dyn Pattern + Sync + Send>);
+
         for path in paths.iter() {
             let relative = path.strip_prefix(&bind.configuration.input)?.to_path_buf();
 
@@ -72,7 +105,7 @@ where P: Pattern + Sync + Send + 'static {
 pub fn select<P>(pattern: P) -> Select<P>
 where P: Pattern + Sync + Send + 'static {
     Select {
-        pattern: pattern,
+        pattern: Arc::new(pattern),
     }
 }
 
@@ -98,6 +131,7 @@ where C: Fn(&Item) -> bool, C: Sync + Send + 'static {
 }
 
 #[inline]
+#[allow(clippy::redundant_field_names)]
 pub fn retain<C>(condition: C) -> Retain<C>
 where C: Fn(&Item) -> bool, C: Copy + Sync + Send + 'static {
     Retain {
@@ -105,21 +139,6 @@ where C: Fn(&Item) -> bool, C: Copy + Sync + Send + 'static {
     }
 }
 
-pub struct PooledEach {}
-
-impl PooledEach {
-    pub fn new() -> PooledEach {
-        PooledEach {}
-    }
-
-    pub fn each<H>(&self, handler: H) -> Each<H>
-    where H: Handle<Item> + Sync + Send + 'static {
-        Each {
-            handler: Arc::new(handler),
-        }
-    }
-}
-
 pub fn each<H>(handler: H) -> Each<H>
 where H: Handle<Item> + Sync + Send + 'static {
     Each {
@@ -134,34 +153,56 @@ where H: Handle<Item> + Sync + Send + 'static {
 
 impl<H> Handle<Bind> for Each<H>
 where H: Handle<Item> + Sync + Send + 'static {
+    #[allow(clippy::mem_replace_with_default)]
     fn handle(&self, bind: &mut Bind) -> ::Result<()> {
         let items = mem::replace(bind.items_mut(), vec![]);
-        let futures: Vec<_> = items
-            .into_iter()
+        let pool = bind.extensions.read().unwrap().get::<WorkerPool>().cloned();
+
+        let tasks =
+            items.into_iter()
             .map(|mut item| {
                 let handler = self.handler.clone();
 
-                let future = future::lazy(move |_| {
+                Box::new(move || {
                     match handler.handle(&mut item) {
-                        Ok(()) => Box::new(future::ok(item)),
-                        Err(e) => Box::new(future::err((e, item))),
+                        Ok(()) => Ok(item),
+                        Err(e) => Err(e.for_item(item.route())),
                     }
-                });
-
-                futures::executor::block_on(futures::executor::spawn_with_handle(future)).unwrap()
+                }) as Box<dyn FnOnce() -> ::Result<Item> + Send>
             })
-            .collect();
-
-        match futures::executor::block_on(future::join_all(futures)) {
-            Ok(mut results) => mem::swap(&mut results, bind.items_mut()),
-            Err((e, item)) => {
-                println!("\nthe following item encountered an error:\n  {:?}\n\n{}\n",
-                            item, e);
-                return Err(e);
+            .collect::<Vec<_>>();
+
+        // outside of a build there's no pool, so process
+        // the items on the current thread
+        let outcomes = match pool {
+            Some(pool) => pool.join(tasks),
+            None => tasks.into_iter().map(|task| Some(task())).collect(),
+        };
+
+        let mut results = Vec::new();
+        let mut errors = Vec::new();
+
+        for outcome in outcomes {
+            match outcome {
+                Some(Ok(item)) => results.push(item),
+                Some(Err(e)) => errors.push(e),
+                None => errors.push(::Error::handler("an item's handler panicked")),
             }
         }
 
-        Ok(())
+        if errors.is_empty() {
+            mem::swap(&mut results, bind.items_mut());
+            return Ok(());
+        }
+
+        if bind.configuration.is_keep_going && errors.len() > 1 {
+            Err(::Error::Aggregate {
+                errors,
+                skipped: vec![],
+            })
+        } else {
+            Err(errors.remove(0))
+        }
     }
 }
 
@@ -176,6 +217,7 @@ where F: Fn(&Item, &Item) -> cmp::Ordering,
     compare: F,
 }
 
+#[allow(clippy::redundant_field_names)]
 pub fn sort_by<F>(compare: F) -> SortBy<F>
 where F: Fn(&Item, &Item) -> cmp::Ordering,
       F: Sync + Send + 'static {
@@ -214,6 +256,7 @@ where B: Ord, F: Fn(&Item) -> B,
     }
 }
 
+#[allow(clippy::redundant_field_names)]
 pub fn sort_by_key<B, F>(key: F) -> SortByKey<B, F>
 where B: Ord, F: Fn(&Item) -> B,
       F: Sync + Send + 'static {
@@ -3,8 +3,8 @@ use std::any::Any;
 use typemap;
 
 use handler::Handle;
-use item::Item;
-use support;
+use item::{Item, Body};
+use util::route;
 
 use super::Extender;
 
@@ -16,66 +16,66 @@ where T: typemap::Key, T::Value: Any + Sync + Send + Clone {
     }
 }
 
+/// Handle<Item> that copies the `Item`'s source to its target.
+///
+/// The item's deferred route, if any, is resolved first.
 pub fn copy(item: &mut Item) -> ::Result<()> {
     use std::fs;
 
-    if let Some(from) = item.source() {
-        if let Some(to) = item.target() {
-            // TODO: once path normalization is in, make sure
-            // writing to output folder
+    route::resolve(item)?;
 
-            if let Some(parent) = to.parent() {
-                // TODO: this errors out if the path already exists? dumb
-                support::mkdir_p(parent).unwrap();
-            }
-
-            fs::copy(from, to)?;
-        }
+    if let Some(from) = item.source()? {
+        item.write_target(|to| fs::copy(&from, to).map(|_| ()))?;
     }
 
     Ok(())
 }
 
-/// Handle<Item> that reads the `Item`'s body.
+/// Handle<Item> that reads the `Item`'s body as text.
+///
+/// Fails if the file isn't valid UTF-8; use `read_bytes`
+/// for binary files.
 pub fn read(item: &mut Item) -> ::Result<()> {
     use std::fs::File;
     use std::io::Read;
 
-    if let Some(from) = item.source() {
+    if let Some(from) = item.source()? {
         let mut buf = String::new();
 
-        // TODO: use try!
-        File::open(from)
-            .unwrap()
-            .read_to_string(&mut buf)
-            .unwrap();
+        File::open(&from)?.read_to_string(&mut buf)
+            .map_err(|e| format!("could not read {:?} as text: {}", from, e))?;
 
-        item.body = buf;
+        item.body = Body::Text(buf);
     }
 
     Ok(())
 }
 
-/// Handle<Item> that writes the `Item`'s body.
-pub fn write(item: &mut Item) -> ::Result<()> {
+/// Handle<Item> that reads the `Item`'s body as raw bytes.
+pub fn read_bytes(item: &mut Item) -> ::Result<()> {
     use std::fs::File;
-    use std::io::Write;
+    use std::io::Read;
+
+    if let Some(from) = item.source()? {
+        let mut buf = Vec::new();
+
+        File::open(from)?.read_to_end(&mut buf)?;
 
-    if let Some(to) = item.target() {
-        // TODO: once path normalization is in, make sure
-        // writing to output folder
-        if let Some(parent) = to.parent() {
-            // TODO: this errors out if the path already exists? dumb
-            support::mkdir_p(parent).unwrap();
-        }
-
-        // TODO: this sometimes crashes
-        File::create(&to)
-            .unwrap()
-            .write_all(item.body.as_bytes())
-            .unwrap();
+        item.body = Body::Bytes(buf);
     }
 
     Ok(())
 }
 
+/// Handle<Item> that writes the `Item`'s body.
+///
+/// The body is written as-is, whether it's text or bytes. The item's
+/// deferred route, if any, is resolved first.
+pub fn write(item: &mut Item) -> ::Result<()> {
+    use std::fs::File;
+    use std::io::Write;
+
+    route::resolve(item)?;
+
+    item.write_target(|to| File::create(to)?.write_all(item.body.as_bytes()))
+}
@@ -11,10 +11,12 @@ pub mod item;
 pub mod bind;
 
 pub struct Chain<T> {
+    #[allow(bare_trait_objects)]
     handlers: Vec<Box<Handle<T> + Sync + Send>>,
 }
 
 impl<T> Chain<T> {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Chain<T> {
         Chain {
             handlers: vec![],
@@ -30,14 +32,15 @@ impl<T> Chain<T> {
 
 impl<T> Handle<T> for Chain<T> {
     fn handle(&self, t: &mut T) -> ::Result<()> {
-        for handler in &self.handlers {
-            handler.handle(t)?;
+        for (position, handler) in self.handlers.iter().enumerate() {
+            handler.handle(t).map_err(|e| e.at_position(position))?;
         }
 
         Ok(())
     }
 }
 
+#[allow(clippy::redundant_field_names)]
 pub fn extend<T>(payload: T::Value) -> Extender<T>
 where T: typemap::Key, T::Value: Any + Sync + Send + Clone {
     Extender {
@@ -61,6 +64,7 @@ where C: Fn(&T) -> bool, C: Sync + Send + 'static,
 impl<C, T, H> Handle<T> for HandleIf<C, T, H>
 where C: Fn(&T) -> bool, C: Sync + Send + 'static,
       H: Handle<T> + Sync + Send + 'static {
+    #[allow(unused_parens)]
     fn handle(&self, t: &mut T) -> ::Result<()> {
         if (self.condition)(t) {
             (self.handler.handle(t))
@@ -71,6 +75,7 @@ where C: Fn(&T) -> bool, C: Sync + Send + 'static,
 }
 
 #[inline]
+#[allow(clippy::redundant_field_names)]
 pub fn handle_if<C, T, H>(condition: C, handler: H) -> HandleIf<C, T, H>
 where C: Fn(&T) -> bool, C: Sync + Send + 'static,
       H: Handle<T> + Sync + Send + 'static {
@@ -1,14 +1,240 @@
-use item::Item;
-use handler::Handle;
+//! Routing.
+//!
+//! Most routers map the path that an item is read from to the path
+//! that it's written to. A `Router` instead sees the whole item, its
+//! extensions and body included, so that it can route by metadata,
+//! e.g. to a folder named after the year a post was published. Since
+//! that metadata is often only available once other handlers have run,
+//! routing can be deferred until just before the item is written.
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::path::{PathBuf, Path};
+use std::sync::Arc;
 
 use regex;
+use typemap;
+
+use item::Item;
+use handler::Handle;
+
+/// Something that determines where an item is written to
+/// based on the entire item.
+pub trait Router {
+    fn route(&self, item: &Item) -> ::Result<PathBuf>;
+}
+
+impl<F> Router for F
+where F: Fn(&Item) -> ::Result<PathBuf> {
+    fn route(&self, item: &Item) -> ::Result<PathBuf> {
+        self(item)
+    }
+}
+
+/// Handle<Item> that routes the item with a `Router`.
+///
+/// Unlike the path-based routers, this routes items that
+/// are only being written as well.
+#[inline]
+pub fn with<R>(router: R) -> With<R>
+where R: Router {
+    With {
+        router,
+    }
+}
+
+pub struct With<R>
+where R: Router {
+    router: R,
+}
+
+impl<R> Handle<Item> for With<R>
+where R: Router {
+    fn handle(&self, item: &mut Item) -> ::Result<()> {
+        let target = self.router.route(item)?;
+        item.route_to(target);
+
+        Ok(())
+    }
+}
+
+/// The router that `resolve` routes an item with.
+pub struct Deferred;
+
+impl typemap::Key for Deferred {
+    type Value = Arc<dyn Router + Sync + Send>;
+}
+
+/// Handle<Item> that defers routing the item with a `Router` until
+/// `resolve`, so that the router sees the result of every handler
+/// in between.
+///
+/// `item::write` and `item::copy` resolve the route before writing.
+#[inline]
+pub fn defer<R>(router: R) -> Defer
+where R: Router + Sync + Send + 'static {
+    Defer {
+        router: Arc::new(router),
+    }
+}
+
+#[derive(Clone)]
+pub struct Defer {
+    router: Arc<dyn Router + Sync + Send>,
+}
+
+impl Handle<Item> for Defer {
+    fn handle(&self, item: &mut Item) -> ::Result<()> {
+        item.extensions.insert::<Deferred>(self.router.clone());
+        Ok(())
+    }
+}
+
+/// Handle<Item> that routes the item with its deferred router, if any.
+pub fn resolve(item: &mut Item) -> ::Result<()> {
+    if let Some(router) = item.extensions.remove::<Deferred>() {
+        let target = router.route(item)?;
+        item.route_to(target);
+    }
+
+    Ok(())
+}
+
+/// A function that produces the value of a template field.
+type Field = Arc<dyn Fn(&Item) -> Option<String> + Sync + Send>;
+
+/// A piece of a route template.
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(String),
+}
+
+/// A router that fills in a template with values from the item.
+///
+/// Each `{name}` placeholder is replaced with the value of the field
+/// registered under that name, e.g. `"{year}/{month}/{slug}/index.html"`.
+/// Use `{{` and `}}` for literal braces.
+///
+/// ``` ignore
+/// route::defer(
+///     route::template("{year}/{slug}/index.html")?
+///     .field::<Year>("year")
+///     .field_with("slug", |item| {
+///         item.extensions.get::<Title>().map(|title| support::slugify(title))
+///     }))
+/// ```
+#[derive(Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+    fields: BTreeMap<String, Field>,
+}
+
+/// Parse a route template.
+#[inline]
+pub fn template(template: &str) -> ::Result<Template> {
+    Template::new(template)
+}
+
+impl Template {
+    /// Parse a route template.
+    ///
+    /// Fails if a brace is unbalanced or a placeholder is empty.
+    pub fn new(template: &str) -> ::Result<Template> {
+        let error = |message: &str| {
+            ::Error::Routing(format!("{} in the route template {:?}", message, template))
+        };
+
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let mut name = String::new();
+
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err(error("unclosed `{`")),
+                            Some(c) => name.push(c),
+                        }
+                    }
 
-// perhaps routing should occur until after all
-// of the handlers run but before the file is (possibly) written
-// and it should take an Item so it could route based on metadata?
-//
-// e.g. to route to a folder named after the year the post was published
+                    if name.is_empty() {
+                        return Err(error("empty placeholder"));
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(literal.clone()));
+                        literal.clear();
+                    }
+
+                    segments.push(Segment::Field(name));
+                },
+                '}' => return Err(error("unmatched `}`")),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template {
+            segments,
+            fields: BTreeMap::new(),
+        })
+    }
+
+    /// Fill in the placeholder with the item's `K` extension.
+    pub fn field<K>(self, name: &str) -> Template
+    where K: typemap::Key, K::Value: Display + Any + Sync + Send + Clone {
+        self.field_with(name, |item: &Item| item.extensions.get::<K>().map(|value| value.to_string()))
+    }
+
+    /// Fill in the placeholder with the result of a function of the item.
+    pub fn field_with<F>(mut self, name: &str, field: F) -> Template
+    where F: Fn(&Item) -> Option<String> + Sync + Send + 'static {
+        self.fields.insert(String::from(name), Arc::new(field));
+        self
+    }
+}
+
+impl Router for Template {
+    fn route(&self, item: &Item) -> ::Result<PathBuf> {
+        let mut path = String::new();
+
+        for segment in &self.segments {
+            match *segment {
+                Segment::Literal(ref literal) => path.push_str(literal),
+                Segment::Field(ref name) => {
+                    let field = self.fields.get(name).ok_or_else(|| {
+                        ::Error::Routing(format!("the route template has no field `{}`", name))
+                    })?;
+
+                    let value = field(item).ok_or_else(|| {
+                        ::Error::Routing(format!("the item has no `{}` to route by", name))
+                    })?;
+
+                    path.push_str(&value);
+                },
+            }
+        }
+
+        Ok(PathBuf::from(path))
+    }
+}
 
 /// file.txt -> file.txt
 /// gen.route(Identity)
@@ -49,6 +275,7 @@ pub fn pretty_page(item: &mut Item) -> ::Result<()> {
 }
 
 #[inline]
+#[allow(clippy::redundant_field_names)]
 pub fn set_extension(extension: &'static str) -> SetExtension {
     SetExtension {
         extension: extension,
@@ -72,41 +299,199 @@ impl Handle<Item> for SetExtension {
     }
 }
 
-/// regex expansion
+/// Route with a regular expression's captures.
+///
+/// The path being read from is matched against the regex and the
+/// template is expanded with its captures, e.g. `$name` or `${name}`
+/// for a named group and `$1` for a numbered one.
 ///
-/// gen.route(
-///     RegexRoute::new(
-///         regex!("/posts/post-(?P<name>.+)\.markdown"),
-///         "/target/$name.html"));
+/// ``` ignore
+/// route::Regex::new(
+///     Regex::new(r"^posts/post-(?P<name>.+)\.markdown$")?,
+///     "target/$name.html")?
+/// ```
 #[derive(Clone)]
 pub struct Regex {
     regex: regex::Regex,
+    template: String,
 
-    // perhaps use regex::Replacer instead?
-    // http://doc.rust-lang.org/regex/regex/trait.Replacer.html
-    template: &'static str,
+    /// Whether to leave the route of an item whose
+    /// path doesn't match untouched instead of failing
+    ignore_unmatched: bool,
 }
 
 impl Regex {
-    pub fn new(regex: regex::Regex, template: &'static str) -> Regex {
-        Regex {
+    /// Fails if the template refers to a group that the regex doesn't capture.
+    #[allow(clippy::redundant_field_names)]
+    pub fn new<S>(regex: regex::Regex, template: S) -> ::Result<Regex>
+    where S: Into<String> {
+        let template = template.into();
+
+        for group in references(&template) {
+            let is_captured = match group.parse::<usize>() {
+                Ok(index) => index < regex.captures_len(),
+                Err(_) => regex.capture_names().any(|name| name == Some(group)),
+            };
+
+            if !is_captured {
+                return Err(::Error::Routing(
+                    format!("the route template {:?} refers to `{}`, \
+                             which isn't captured by {:?}",
+                            template, group, regex.as_str())));
+            }
+        }
+
+        Ok(Regex {
             regex: regex,
             template: template,
-        }
+            ignore_unmatched: false,
+        })
+    }
+
+    /// Leave the routes of items whose path doesn't match untouched
+    /// instead of failing with a routing error.
+    pub fn ignore_unmatched(mut self, ignore_unmatched: bool) -> Regex {
+        self.ignore_unmatched = ignore_unmatched;
+        self
+    }
+
+    /// Expand the template for a path, if the regex matches it.
+    fn expand(&self, path: &Path) -> Option<PathBuf> {
+        let captures = path.to_str().and_then(|path| self.regex.captures(path))?;
+        let mut expanded = String::new();
+
+        captures.expand(&self.template, &mut expanded);
+
+        Some(PathBuf::from(expanded))
     }
 }
 
 impl Handle<Item> for Regex {
     fn handle(&self, item: &mut Item) -> ::Result<()> {
-        item.route_with(|path: &Path| -> PathBuf {
-            let caps = self.regex.captures(path.to_str().unwrap()).unwrap();
-            let mut expanded = String::new();
-
-            caps.expand(self.template, &mut expanded);
+        let target = match item.route().reading() {
+            Some(from) => self.expand(from).ok_or_else(|| from.to_path_buf()),
+            None => return Ok(()),
+        };
 
-            PathBuf::from(&expanded)
-        });
+        match target {
+            Ok(target) => item.route_to(target),
+            Err(_) if self.ignore_unmatched => (),
+            Err(from) => {
+                return Err(::Error::Routing(
+                    format!("{:?} doesn't match {:?}", from, self.regex.as_str())));
+            },
+        }
 
         Ok(())
     }
 }
+
+/// The groups that a regex replacement template refers to.
+///
+/// This follows the syntax of `regex::Captures::expand`: `$$` is a
+/// literal `$`, and a `$` that isn't followed by a group is literal.
+fn references(template: &str) -> Vec<&str> {
+    let is_name = |c: char| c == '_' || c.is_ascii_alphanumeric();
+    let mut groups = vec![];
+    let mut rest = template;
+
+    while let Some(idx) = rest.find('$') {
+        rest = &rest[idx + 1 ..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            rest = after;
+        } else if let Some(braced) = rest.strip_prefix('{') {
+            if let Some(end) = braced.find('}') {
+                if end > 0 {
+                    groups.push(&braced[.. end]);
+                }
+
+                rest = &braced[end + 1 ..];
+            }
+        } else {
+            let end = rest.find(|c: char| !is_name(c)).unwrap_or(rest.len());
+
+            if end > 0 {
+                groups.push(&rest[.. end]);
+            }
+
+            rest = &rest[end ..];
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use typemap;
+
+    use item::Item;
+    use handler::Handle;
+    use regex;
+
+    use super::{Regex, defer, resolve, template};
+
+    struct Year;
+
+    impl typemap::Key for Year {
+        type Value = u32;
+    }
+
+    #[test]
+    fn route_by_metadata() {
+        let router =
+            template("{year}/{slug}/index.html").unwrap()
+            .field::<Year>("year")
+            .field_with("slug", |item: &Item| {
+                item.route().reading()
+                .and_then(|path| path.file_stem())
+                .map(|stem| stem.to_string_lossy().into_owned())
+            });
+
+        let mut item = Item::reading("posts/hello.md");
+
+        defer(router).handle(&mut item).unwrap();
+
+        // the year isn't known until after routing was deferred
+        item.extensions.insert::<Year>(2024);
+        resolve(&mut item).unwrap();
+
+        assert_eq!(item.route().writing(), Some(Path::new("2024/hello/index.html")));
+
+        let mut untagged = Item::reading("posts/untagged.md");
+
+        defer(template("{year}/{{x}}").unwrap().field::<Year>("year"))
+            .handle(&mut untagged).unwrap();
+
+        assert!(resolve(&mut untagged).is_err());
+
+        for invalid in &["{year", "year}", "{}/index.html"] {
+            assert!(template(invalid).is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn route_with_captures() {
+        let posts = regex::Regex::new(r"^posts/post-(?P<name>.+)\.markdown$").unwrap();
+
+        let router = Regex::new(posts.clone(), String::from("target/${name}.html")).unwrap();
+
+        let mut item = Item::reading("posts/post-hello.markdown");
+        router.handle(&mut item).unwrap();
+
+        assert_eq!(item.route().writing(), Some(Path::new("target/hello.html")));
+
+        let mut unmatched = Item::reading("pages/about.markdown");
+
+        assert!(router.handle(&mut unmatched).is_err());
+        assert!(router.ignore_unmatched(true).handle(&mut unmatched).is_ok());
+        assert_eq!(unmatched.route().writing(), None);
+
+        assert!(Regex::new(posts.clone(), "$$name/$1.html").is_ok());
+        assert!(Regex::new(posts.clone(), "$title.html").is_err());
+        assert!(Regex::new(posts, "$2.html").is_err());
+    }
+}